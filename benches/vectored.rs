@@ -0,0 +1,164 @@
+#![feature(async_await, test)]
+//! Compares writing an HTTP-style header and body as two `write_all` calls
+//! against a single `write_vectored` call, counting the TLS records and
+//! transport writes each produces. Each benchmark asserts how many records
+//! a response takes: two with `write_all`, one with `write_vectored`.
+extern crate test;
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::Context;
+
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, IoSlice};
+use futures::future::poll_fn;
+use futures::{FutureExt, Poll, TryFutureExt};
+use test::Bencher;
+use tls_async::testing::{duplex, localhost_contexts, DuplexStream, TestCa};
+use tls_async::TlsStream;
+use tokio::runtime::current_thread::Runtime;
+
+const HEADER: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 512\r\n\r\n";
+const BODY: [u8; 512] = [0u8; 512];
+
+#[derive(Default)]
+struct Counts {
+    writes: AtomicUsize,
+    records: AtomicUsize,
+}
+
+/// A transport counting its writes and the TLS records written through it.
+struct Counting {
+    inner: DuplexStream,
+    counts: Arc<Counts>,
+    // Bytes of the current record header seen so far, and bytes of the
+    // current record body still to come.
+    header: Vec<u8>,
+    remaining: usize,
+}
+
+impl Counting {
+    fn observe(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            if self.remaining > 0 {
+                let sz = std::cmp::min(self.remaining, buf.len());
+                self.remaining -= sz;
+                buf = &buf[sz..];
+                continue;
+            }
+            self.header.push(buf[0]);
+            buf = &buf[1..];
+            if self.header.len() == 5 {
+                self.remaining = (self.header[3] as usize) << 8 | self.header[4] as usize;
+                self.header.clear();
+                self.counts.records.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl AsyncRead for Counting {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Counting {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(sz)) = res {
+            self.counts.writes.fetch_add(1, Ordering::Relaxed);
+            self.observe(&buf[..sz]);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl std::fmt::Debug for Counting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Counting").finish()
+    }
+}
+
+struct Pair {
+    rt: Runtime,
+    client: TlsStream<DuplexStream>,
+    server: TlsStream<Counting>,
+    counts: Arc<Counts>,
+}
+
+fn pair() -> Pair {
+    let ca = TestCa::new().unwrap();
+    let (acceptor, connector) = localhost_contexts(&ca, &ca).unwrap();
+
+    // Responses are written by the server, through the counting transport.
+    let (client, server) = duplex(1024 * 1024);
+    let counts = Arc::new(Counts::default());
+    let server = Counting {
+        inner: server,
+        counts: counts.clone(),
+        header: Vec::new(),
+        remaining: 0,
+    };
+    let mut rt = Runtime::new().unwrap();
+    let fut = futures::future::try_join(connector.connect("localhost", client), acceptor.accept(server));
+    let (client, server) = rt.block_on(fut.boxed().compat()).unwrap();
+    Pair { rt, client, server, counts }
+}
+
+fn report(name: &str, counts: &Counts, iterations: usize, records_per_response: usize) {
+    let records = counts.records.load(Ordering::Relaxed);
+    println!(
+        "{}: {:.2} records and {:.2} transport writes per response",
+        name,
+        records as f64 / iterations as f64,
+        counts.writes.load(Ordering::Relaxed) as f64 / iterations as f64,
+    );
+    assert_eq!(records, records_per_response * iterations, "{}: unexpected record count", name);
+}
+
+fn run<F>(b: &mut Bencher, name: &str, records_per_response: usize, write: F)
+    where F: Fn(&mut TlsStream<Counting>) -> Pin<Box<dyn futures::Future<Output = io::Result<()>> + '_>>,
+{
+    let Pair { mut rt, mut client, mut server, counts } = pair();
+    counts.records.store(0, Ordering::Relaxed);
+    counts.writes.store(0, Ordering::Relaxed);
+    let mut iterations = 0;
+    b.iter(|| {
+        iterations += 1;
+        let mut buf = vec![0u8; HEADER.len() + BODY.len()];
+        let fut = async {
+            write(&mut server).await?;
+            client.read_exact(&mut buf).await
+        };
+        rt.block_on(fut.boxed_local().compat()).unwrap();
+    });
+    report(name, &counts, iterations, records_per_response);
+}
+
+#[bench]
+fn header_then_body(b: &mut Bencher) {
+    run(b, "write_all x2", 2, |stream| Box::pin(async move {
+        stream.write_all(HEADER).await?;
+        stream.write_all(&BODY).await
+    }));
+}
+
+#[bench]
+fn vectored(b: &mut Bencher) {
+    run(b, "write_vectored", 1, |stream| Box::pin(async move {
+        let bufs = [IoSlice::new(HEADER), IoSlice::new(&BODY)];
+        let sz = poll_fn(|cx| Pin::new(&mut *stream).poll_write_vectored(cx, &bufs)).await?;
+        assert_eq!(sz, HEADER.len() + BODY.len());
+        Ok(())
+    }));
+}
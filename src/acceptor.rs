@@ -1,12 +1,20 @@
+use crate::authorizer::Authorizer;
+use crate::config::StreamConfig;
 use crate::errors::Error;
+use crate::metrics::{Metrics, Role, SharedMetrics};
 use crate::pending::PendingTlsStream;
-use crate::{Identity, Protocol};
+use crate::timeout::Timer;
+use crate::{Identity, Protocol, RecordSizing};
 
-use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::io::{AsyncRead, AsyncWrite};
 
 /// A builder for `TlsAcceptor`s.
 pub struct TlsAcceptorBuilder {
     inner: native_tls::TlsAcceptorBuilder,
+    config: StreamConfig,
 }
 
 impl TlsAcceptorBuilder {
@@ -30,11 +38,122 @@ impl TlsAcceptorBuilder {
         self
     }
 
+    /// Sets the `Authorizer` used to map a client's certificate to a
+    /// `Principal` after each handshake.
+    ///
+    /// Connections rejected by the authorizer resolve to `Error::Unauthorized`.
+    ///
+    /// `native-tls` never requests a certificate from clients, so the
+    /// authorizer currently always receives `None`.
+    ///
+    /// Defaults to no authorizer, in which case every completed handshake is
+    /// accepted and `TlsStream::principal` returns `None`.
+    pub fn authorizer<A>(&mut self, authorizer: A) -> &mut TlsAcceptorBuilder
+        where A: Authorizer + 'static,
+    {
+        self.config.authorizer = Some(Arc::new(authorizer));
+        self
+    }
+
+    /// Controls whether a connection closed without a TLS `close_notify` is
+    /// reported as an error.
+    ///
+    /// When enabled, reading from a `TlsStream` whose transport reaches EOF
+    /// before the peer sent `close_notify` fails with
+    /// `io::ErrorKind::UnexpectedEof` instead of returning `Ok(0)`. This
+    /// protects against truncation by an attacker or middlebox, but must be
+    /// disabled for legacy peers that never send `close_notify`, after which
+    /// `TlsStream::is_truncated` still tells the two cases apart.
+    ///
+    /// Defaults to `true`.
+    pub fn require_close_notify(&mut self, require_close_notify: bool) -> &mut TlsAcceptorBuilder {
+        self.config.require_close_notify = require_close_notify;
+        self
+    }
+
+    /// Sets how data written to each `TlsStream` is split into TLS records.
+    ///
+    /// Defaults to `RecordSizing::Unbuffered`.
+    pub fn record_sizing(&mut self, record_sizing: RecordSizing) -> &mut TlsAcceptorBuilder {
+        self.config.record_sizing = record_sizing;
+        self
+    }
+
+    /// Sets the observer notified of handshakes and traffic on every
+    /// connection created by the TlsAcceptor.
+    ///
+    /// Defaults to no observer.
+    pub fn metrics(&mut self, metrics: Arc<dyn Metrics>) -> &mut TlsAcceptorBuilder {
+        self.config.metrics = Some(SharedMetrics(metrics));
+        self
+    }
+
+    /// Sets the `Timer` used to enforce the timeouts of every `TlsStream`
+    /// created by the TlsAcceptor.
+    ///
+    /// Timeouts have no effect until a timer is set.
+    pub fn timer(&mut self, timer: Arc<dyn Timer>) -> &mut TlsAcceptorBuilder {
+        self.config.timeouts.timer = Some(timer);
+        self
+    }
+
+    /// Sets how long a `TlsStream` may go without completing any read or
+    /// write while an operation is pending, before failing it with
+    /// `io::ErrorKind::TimedOut`.
+    ///
+    /// Defaults to `None`, meaning no timeout.
+    pub fn idle_timeout(&mut self, timeout: Option<Duration>) -> &mut TlsAcceptorBuilder {
+        self.config.timeouts.idle = timeout;
+        self
+    }
+
+    /// Sets how long a read on a `TlsStream` may stay pending before failing
+    /// with `io::ErrorKind::TimedOut`.
+    ///
+    /// Defaults to `None`, meaning no timeout.
+    pub fn read_timeout(&mut self, timeout: Option<Duration>) -> &mut TlsAcceptorBuilder {
+        self.config.timeouts.read = timeout;
+        self
+    }
+
+    /// Sets how long a write, flush or close on a `TlsStream` may stay
+    /// pending before failing with `io::ErrorKind::TimedOut`.
+    ///
+    /// Defaults to `None`, meaning no timeout.
+    pub fn write_timeout(&mut self, timeout: Option<Duration>) -> &mut TlsAcceptorBuilder {
+        self.config.timeouts.write = timeout;
+        self
+    }
+
+    /// Sets how long a handshake started by the TlsAcceptor may take before
+    /// failing with `Error::HandshakeTimeout`.
+    ///
+    /// Like the other timeouts, this has no effect until a timer is set.
+    ///
+    /// Defaults to `None`, meaning no timeout.
+    pub fn handshake_timeout(&mut self, timeout: Option<Duration>) -> &mut TlsAcceptorBuilder {
+        self.config.timeouts.handshake = timeout;
+        self
+    }
+
+    /// Controls whether the transport of a failed handshake can be taken
+    /// back with `PendingTlsStream::take_transport`.
+    ///
+    /// When enabled, the bytes read from the transport during each handshake
+    /// are kept until it completes.
+    ///
+    /// Defaults to `false`.
+    pub fn recover_transport(&mut self, recover_transport: bool) -> &mut TlsAcceptorBuilder {
+        self.config.recover_transport = recover_transport;
+        self
+    }
+
     /// Creates a new `TlsAcceptor`.
     pub fn build(&self) -> Result<TlsAcceptor, Error> {
         let acceptor = self.inner.build().map_err(Error::Acceptor)?;
         Ok(TlsAcceptor {
-            inner: acceptor
+            inner: acceptor,
+            config: self.config.clone(),
         })
     }
 }
@@ -84,6 +203,7 @@ impl TlsAcceptorBuilder {
 #[derive(Clone)]
 pub struct TlsAcceptor {
     inner: native_tls::TlsAcceptor,
+    config: StreamConfig,
 }
 
 impl TlsAcceptor {
@@ -94,6 +214,7 @@ impl TlsAcceptor {
         let native_acceptor = native_tls::TlsAcceptor::new(identity).map_err(Error::Acceptor)?;
         Ok(TlsAcceptor {
             inner: native_acceptor,
+            config: StreamConfig::default(),
         })
     }
 
@@ -104,6 +225,7 @@ impl TlsAcceptor {
         let builder = native_tls::TlsAcceptor::builder(identity);
         TlsAcceptorBuilder {
             inner: builder,
+            config: StreamConfig::default(),
         }
     }
 
@@ -117,10 +239,15 @@ impl TlsAcceptor {
     /// This is typically used after a new socket has been accepted from a
     /// `TcpListener`. That socket is then passed to this function to perform
     /// the server half of accepting a client connection.
+    ///
+    /// If an `Authorizer` was configured, it is consulted once the handshake
+    /// completes and its `Principal` is available from the `TlsStream`.
     pub fn accept<S>(&self, stream: S) -> PendingTlsStream<S>
-        where S: AsyncRead + AsyncWrite + Unpin,
+        where S: AsyncRead + AsyncWrite,
     {
-        PendingTlsStream::new(self.inner.accept(stream.compat()))
+        PendingTlsStream::new(Role::Server, None, self.config.clone(), stream, |adapter| {
+            self.inner.accept(adapter)
+        })
     }
 }
 
@@ -128,6 +255,7 @@ impl From<native_tls::TlsAcceptor> for TlsAcceptor {
     fn from(inner: native_tls::TlsAcceptor) -> Self {
         Self {
             inner,
+            config: StreamConfig::default(),
         }
     }
 }
\ No newline at end of file
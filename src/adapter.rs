@@ -0,0 +1,234 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::task::noop_waker_ref;
+
+/// The transport handed to `native-tls`, wrapping the user's stream `S`.
+///
+/// The stream is pinned on the heap, so `S` need not be `Unpin`, while the
+/// adapter, and with it `PendingTlsStream` and `TlsStream`, is `Unpin` for
+/// any `S`.
+///
+/// `native-tls` drives the transport through `Read`/`Write`, which have no
+/// `Context`, so the adapter polls it with the waker of the task that last
+/// polled the TLS stream, set by `set_waker`, and reports `Pending` as
+/// `io::ErrorKind::WouldBlock`. This works with any executor.
+///
+/// Besides bridging `AsyncRead`/`AsyncWrite` to `Read`/`Write`, this records
+/// whether the transport has reported EOF, which lets `TlsStream` tell a
+/// peer's `close_notify` apart from a truncated connection.
+#[derive(Debug)]
+pub struct StdAdapter<S> {
+    // Only `None` while the adapter is dropped.
+    inner: Option<Pin<Box<S>>>,
+    // Registered with the transport whenever it is not ready.
+    waker: Option<Waker>,
+    eof: bool,
+    recovery: Option<Arc<Recovery<S>>>,
+}
+
+impl<S> StdAdapter<S> {
+    pub(crate) fn new(inner: S, recovery: Option<Arc<Recovery<S>>>) -> Self {
+        StdAdapter {
+            inner: Some(Box::pin(inner)),
+            waker: None,
+            eof: false,
+            recovery,
+        }
+    }
+
+    /// Sets the waker to register with the transport when it is not ready.
+    ///
+    /// Called with the task's context before every call into `native-tls`.
+    /// Until then, the transport is polled with a waker that does nothing.
+    pub(crate) fn set_waker(&mut self, waker: &Waker) {
+        match self.waker {
+            Some(ref current) if current.will_wake(waker) => {}
+            _ => self.waker = Some(waker.clone()),
+        }
+    }
+
+    fn poll_io<T, F>(&mut self, f: F) -> io::Result<T>
+        where F: FnOnce(Pin<&mut S>, &mut Context<'_>) -> Poll<io::Result<T>>,
+    {
+        let waker = match self.waker {
+            Some(ref waker) => waker,
+            None => noop_waker_ref(),
+        };
+        let mut cx = Context::from_waker(waker);
+        let inner = self.inner.as_mut().expect("transport was taken").as_mut();
+        match f(inner, &mut cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    /// Get access to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        self.inner.as_ref().expect("transport was taken")
+    }
+
+    /// Get mutable access to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S
+        where S: Unpin,
+    {
+        self.inner.as_mut().expect("transport was taken")
+    }
+
+    /// Get pinned mutable access to the underlying stream.
+    pub fn get_pin_mut(&mut self) -> Pin<&mut S> {
+        self.inner.as_mut().expect("transport was taken").as_mut()
+    }
+
+    /// Returns `true` once a read from the underlying stream returned EOF.
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+}
+
+impl<S: AsyncRead> Read for StdAdapter<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let sz = self.poll_io(|inner, cx| inner.poll_read(cx, buf))?;
+        if sz == 0 && !buf.is_empty() {
+            self.eof = true;
+        }
+        if let Some(ref recovery) = self.recovery {
+            if !recovery.record(&buf[..sz]) {
+                self.recovery = None;
+            }
+        }
+        Ok(sz)
+    }
+}
+
+impl<S: AsyncWrite> Write for StdAdapter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.poll_io(|inner, cx| inner.poll_write(cx, buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.poll_io(|inner, cx| inner.poll_flush(cx))
+    }
+}
+
+impl<S> Drop for StdAdapter<S> {
+    fn drop(&mut self) {
+        if let (Some(recovery), Some(inner)) = (self.recovery.take(), self.inner.take()) {
+            recovery.deposit(inner);
+        }
+    }
+}
+
+/// Receives the transport of a handshake that did not complete.
+///
+/// While armed, a `Recovery` records the bytes read from the transport, and
+/// gets the transport back when `native-tls` drops its `StdAdapter`. It is
+/// disarmed once the handshake succeeds, after which the adapter stops
+/// recording and releases it.
+pub(crate) struct Recovery<S> {
+    state: Mutex<RecoveryState<S>>,
+}
+
+struct RecoveryState<S> {
+    armed: bool,
+    read: Vec<u8>,
+    transport: Option<Pin<Box<S>>>,
+}
+
+impl<S> Recovery<S> {
+    pub(crate) fn new() -> Self {
+        Recovery {
+            state: Mutex::new(RecoveryState {
+                armed: true,
+                read: Vec::new(),
+                transport: None,
+            }),
+        }
+    }
+
+    /// Records bytes read from the transport, returning `false` once
+    /// disarmed.
+    fn record(&self, buf: &[u8]) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.armed {
+            state.read.extend_from_slice(buf);
+        }
+        state.armed
+    }
+
+    fn deposit(&self, transport: Pin<Box<S>>) {
+        let mut state = self.state.lock().unwrap();
+        if state.armed {
+            state.transport = Some(transport);
+        }
+    }
+
+    pub(crate) fn disarm(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.armed = false;
+        state.read = Vec::new();
+    }
+
+    /// Takes the transport, if it was handed back.
+    pub(crate) fn take(&self) -> Option<RecoveredTransport<S>> {
+        let mut state = self.state.lock().unwrap();
+        let transport = state.transport.take()?;
+        Some(RecoveredTransport {
+            transport,
+            read: std::mem::replace(&mut state.read, Vec::new()),
+        })
+    }
+}
+
+impl<S> fmt::Debug for Recovery<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recovery").finish()
+    }
+}
+
+/// The transport of a failed handshake, returned by
+/// `PendingTlsStream::take_transport`.
+///
+/// Besides the stream itself, this holds every byte read from it during the
+/// handshake, e.g. the start of a plaintext request sent by a client which
+/// does not speak TLS.
+pub struct RecoveredTransport<S> {
+    transport: Pin<Box<S>>,
+    read: Vec<u8>,
+}
+
+impl<S> RecoveredTransport<S> {
+    /// Returns the bytes read from the transport during the handshake.
+    pub fn bytes_read(&self) -> &[u8] {
+        &self.read
+    }
+
+    /// Get pinned mutable access to the transport.
+    pub fn get_pin_mut(&mut self) -> Pin<&mut S> {
+        self.transport.as_mut()
+    }
+
+    /// Returns the pinned transport and the bytes read from it.
+    pub fn into_parts(self) -> (Pin<Box<S>>, Vec<u8>) {
+        (self.transport, self.read)
+    }
+
+    /// Returns the transport and the bytes read from it.
+    pub fn into_inner(self) -> (S, Vec<u8>)
+        where S: Unpin,
+    {
+        (*Pin::into_inner(self.transport), self.read)
+    }
+}
+
+impl<S> fmt::Debug for RecoveredTransport<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecoveredTransport")
+            .field("bytes_read", &self.read.len())
+            .finish()
+    }
+}
@@ -0,0 +1,71 @@
+use crate::Certificate;
+
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+/// An application-defined identity for the peer of a `TlsStream`.
+///
+/// A principal is produced by an `Authorizer` once the handshake completes
+/// and is stored on the resulting `TlsStream`, where downstream handlers can
+/// retrieve it with `TlsStream::principal`.
+#[derive(Clone)]
+pub struct Principal {
+    inner: Arc<dyn Any + Send + Sync>,
+}
+
+impl Principal {
+    /// Wraps an application-defined value as a principal.
+    pub fn new<T: Any + Send + Sync>(value: T) -> Principal {
+        Principal {
+            inner: Arc::new(value),
+        }
+    }
+
+    /// Returns a reference to the wrapped value if it is of type `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.inner.downcast_ref::<T>()
+    }
+
+    /// Returns `true` if the wrapped value is of type `T`.
+    pub fn is<T: Any>(&self) -> bool {
+        self.inner.is::<T>()
+    }
+}
+
+impl fmt::Debug for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Principal").finish()
+    }
+}
+
+/// Maps the certificate presented by a client to a `Principal`.
+///
+/// An authorizer is installed with `TlsAcceptorBuilder::authorizer` and is
+/// invoked by `TlsAcceptor` after every successful handshake. Returning an
+/// error rejects the connection, which then resolves to
+/// `Error::Unauthorized` with the returned reason.
+///
+/// `native-tls` only exposes the leaf certificate of the peer, so that is
+/// what the authorizer receives. It is `None` if the client did not present
+/// a certificate, which is currently always the case: `native-tls` has no
+/// way to make a `TlsAcceptor` request a certificate from clients. Until it
+/// does, an authorizer can only decide based on the absence of one.
+///
+/// Parsing the certificate (e.g. for the CN, a SAN URI or a SPIFFE ID) is
+/// left to the implementation, starting from `Certificate::to_der`.
+///
+/// Closures of the form `Fn(Option<&Certificate>) -> Result<Principal, String>`
+/// implement this trait.
+pub trait Authorizer: Send + Sync {
+    /// Decides who the peer is, or why it is rejected.
+    fn authorize(&self, peer_certificate: Option<&Certificate>) -> Result<Principal, String>;
+}
+
+impl<F> Authorizer for F
+    where F: Fn(Option<&Certificate>) -> Result<Principal, String> + Send + Sync,
+{
+    fn authorize(&self, peer_certificate: Option<&Certificate>) -> Result<Principal, String> {
+        (self)(peer_certificate)
+    }
+}
@@ -0,0 +1,37 @@
+use crate::authorizer::Authorizer;
+use crate::metrics::SharedMetrics;
+use crate::record_sizing::RecordSizing;
+#[cfg(feature = "crl")]
+use crate::revocation::RevocationConfig;
+use crate::timeout::TimeoutConfig;
+
+use std::sync::Arc;
+
+/// Settings shared by a `TlsConnector` or `TlsAcceptor` with every
+/// `PendingTlsStream` and `TlsStream` it creates.
+#[derive(Clone)]
+pub(crate) struct StreamConfig {
+    pub(crate) authorizer: Option<Arc<dyn Authorizer>>,
+    pub(crate) require_close_notify: bool,
+    pub(crate) record_sizing: RecordSizing,
+    pub(crate) metrics: Option<SharedMetrics>,
+    pub(crate) timeouts: TimeoutConfig,
+    pub(crate) recover_transport: bool,
+    #[cfg(feature = "crl")]
+    pub(crate) revocation: RevocationConfig,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        StreamConfig {
+            authorizer: None,
+            require_close_notify: true,
+            record_sizing: RecordSizing::default(),
+            metrics: None,
+            timeouts: TimeoutConfig::default(),
+            recover_transport: false,
+            #[cfg(feature = "crl")]
+            revocation: RevocationConfig::default(),
+        }
+    }
+}
@@ -1,12 +1,28 @@
+use crate::config::StreamConfig;
+#[cfg(feature = "romio")]
+use crate::dial::{split_host_port, Dial};
 use crate::errors::Error;
+use crate::metrics::{Metrics, Role, SharedMetrics};
 use crate::pending::PendingTlsStream;
-use crate::{Certificate, Identity, Protocol};
+#[cfg(feature = "crl")]
+use crate::revocation::{CertificateRevocationList, RevocationPolicy};
+use crate::timeout::Timer;
+use crate::{Certificate, Identity, Protocol, RecordSizing};
+#[cfg(feature = "romio")]
+use crate::TlsStream;
 
-use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "romio")]
+use romio::TcpStream;
 
 /// A builder for `TlsConnector`s.
 pub struct TlsConnectorBuilder {
     inner: native_tls::TlsConnectorBuilder,
+    config: StreamConfig,
 }
 
 impl TlsConnectorBuilder {
@@ -72,6 +88,16 @@ impl TlsConnectorBuilder {
         self
     }
 
+    /// Sets the protocols offered to the server through ALPN, in order of
+    /// preference, e.g. `&["h2", "http/1.1"]`.
+    ///
+    /// The protocol selected by the server is available from
+    /// `TlsStream::negotiated_alpn`. Defaults to no protocols.
+    pub fn request_alpns(&mut self, protocols: &[&str]) -> &mut TlsConnectorBuilder {
+        self.inner.request_alpns(protocols);
+        self
+    }
+
     /// Controls the use of hostname verification.
     ///
     /// Defaults to `false`.
@@ -89,11 +115,151 @@ impl TlsConnectorBuilder {
         self
     }
 
+    /// Controls whether a connection closed without a TLS `close_notify` is
+    /// reported as an error.
+    ///
+    /// When enabled, reading from a `TlsStream` whose transport reaches EOF
+    /// before the server sent `close_notify` fails with
+    /// `io::ErrorKind::UnexpectedEof` instead of returning `Ok(0)`, so a
+    /// response cut short by an attacker or middlebox is not mistaken for a
+    /// complete one. Disable this for legacy servers that never send
+    /// `close_notify`, after which `TlsStream::is_truncated` still tells the
+    /// two cases apart.
+    ///
+    /// Defaults to `true`.
+    pub fn require_close_notify(&mut self, require_close_notify: bool) -> &mut TlsConnectorBuilder {
+        self.config.require_close_notify = require_close_notify;
+        self
+    }
+
+    /// Sets how data written to each `TlsStream` is split into TLS records.
+    ///
+    /// Defaults to `RecordSizing::Unbuffered`.
+    pub fn record_sizing(&mut self, record_sizing: RecordSizing) -> &mut TlsConnectorBuilder {
+        self.config.record_sizing = record_sizing;
+        self
+    }
+
+    /// Sets the observer notified of handshakes and traffic on every
+    /// connection created by the TlsConnector.
+    ///
+    /// Defaults to no observer.
+    pub fn metrics(&mut self, metrics: Arc<dyn Metrics>) -> &mut TlsConnectorBuilder {
+        self.config.metrics = Some(SharedMetrics(metrics));
+        self
+    }
+
+    /// Sets the `Timer` used to enforce the timeouts of every `TlsStream`
+    /// created by the TlsConnector.
+    ///
+    /// Timeouts have no effect until a timer is set.
+    pub fn timer(&mut self, timer: Arc<dyn Timer>) -> &mut TlsConnectorBuilder {
+        self.config.timeouts.timer = Some(timer);
+        self
+    }
+
+    /// Sets how long a `TlsStream` may go without completing any read or
+    /// write while an operation is pending, before failing it with
+    /// `io::ErrorKind::TimedOut`.
+    ///
+    /// Defaults to `None`, meaning no timeout.
+    pub fn idle_timeout(&mut self, timeout: Option<Duration>) -> &mut TlsConnectorBuilder {
+        self.config.timeouts.idle = timeout;
+        self
+    }
+
+    /// Sets how long a read on a `TlsStream` may stay pending before failing
+    /// with `io::ErrorKind::TimedOut`.
+    ///
+    /// Defaults to `None`, meaning no timeout.
+    pub fn read_timeout(&mut self, timeout: Option<Duration>) -> &mut TlsConnectorBuilder {
+        self.config.timeouts.read = timeout;
+        self
+    }
+
+    /// Sets how long a write, flush or close on a `TlsStream` may stay
+    /// pending before failing with `io::ErrorKind::TimedOut`.
+    ///
+    /// Defaults to `None`, meaning no timeout.
+    pub fn write_timeout(&mut self, timeout: Option<Duration>) -> &mut TlsConnectorBuilder {
+        self.config.timeouts.write = timeout;
+        self
+    }
+
+    /// Sets how long a handshake started by the TlsConnector may take before
+    /// failing with `Error::HandshakeTimeout`.
+    ///
+    /// Like the other timeouts, this has no effect until a timer is set.
+    ///
+    /// Defaults to `None`, meaning no timeout.
+    pub fn handshake_timeout(&mut self, timeout: Option<Duration>) -> &mut TlsConnectorBuilder {
+        self.config.timeouts.handshake = timeout;
+        self
+    }
+
+    /// Adds a CRL against which the server's certificate is checked after
+    /// each handshake.
+    ///
+    /// `native-tls` only exposes the peer's leaf certificate, so that is the
+    /// only certificate checked; intermediates are not, and there is no way
+    /// to ask for the whole chain to be checked. Revoked certificates are
+    /// rejected with `Error::Revoked`.
+    ///
+    /// The CRL's signature is not verified, so it must come from a trusted
+    /// source. See `CertificateRevocationList` for details.
+    ///
+    /// Defaults to no CRLs, in which case nothing is checked.
+    #[cfg(feature = "crl")]
+    pub fn add_crl(&mut self, crl: CertificateRevocationList) -> &mut TlsConnectorBuilder {
+        self.config.revocation.crls.push(Arc::new(crl));
+        self
+    }
+
+    /// Sets how a server certificate not covered by an up-to-date CRL is
+    /// treated once at least one CRL was added.
+    ///
+    /// Defaults to `RevocationPolicy::HardFail`.
+    #[cfg(feature = "crl")]
+    pub fn revocation_policy(&mut self, policy: RevocationPolicy) -> &mut TlsConnectorBuilder {
+        self.config.revocation.policy = policy;
+        self
+    }
+
+    /// Sets how long `TlsConnector::connect_to` may take to resolve the host
+    /// and establish a TCP connection, before failing with
+    /// `io::ErrorKind::TimedOut`.
+    ///
+    /// Like the other timeouts, this needs a timer, set with
+    /// `TlsConnectorBuilder::timer`. Without one it is ignored, and
+    /// `connect_to` waits for as long as the system resolver and TCP stack
+    /// do.
+    ///
+    /// Defaults to `None`, meaning no timeout.
+    #[cfg(feature = "romio")]
+    pub fn connect_timeout(&mut self, timeout: Option<Duration>) -> &mut TlsConnectorBuilder {
+        self.config.timeouts.connect = timeout;
+        self
+    }
+
+    /// Controls whether the transport of a failed handshake can be taken
+    /// back with `PendingTlsStream::take_transport`.
+    ///
+    /// When enabled, the bytes read from the transport during each handshake
+    /// are kept until it completes.
+    ///
+    /// Defaults to `false`.
+    pub fn recover_transport(&mut self, recover_transport: bool) -> &mut TlsConnectorBuilder {
+        self.config.recover_transport = recover_transport;
+        self
+    }
+
     /// Creates a new `TlsConnector`.
     pub fn build(&self) -> Result<TlsConnector, Error> {
         let connector = self.inner.build().map_err(Error::Connector)?;
         Ok(TlsConnector {
-            inner: connector
+            inner: connector,
+            config: self.config.clone(),
+            id: next_connector_id(),
         })
     }
 }
@@ -125,6 +291,9 @@ impl TlsConnectorBuilder {
 #[derive(Clone)]
 pub struct TlsConnector {
     inner: native_tls::TlsConnector,
+    config: StreamConfig,
+    // Identifies the configuration, shared by clones, for `Pool` keys.
+    id: u64,
 }
 
 impl TlsConnector {
@@ -133,6 +302,8 @@ impl TlsConnector {
         let native_connector = native_tls::TlsConnector::new().map_err(Error::Connector)?;
         Ok( TlsConnector {
             inner: native_connector,
+            config: StreamConfig::default(),
+            id: next_connector_id(),
         })
     }
 
@@ -140,9 +311,14 @@ impl TlsConnector {
     pub fn builder() -> TlsConnectorBuilder {
         TlsConnectorBuilder {
             inner: native_tls::TlsConnector::builder(),
+            config: StreamConfig::default(),
         }
     }
 
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
     /// Connects the provided stream with this connector, assuming the provided
     /// domain.
     ///
@@ -156,8 +332,45 @@ impl TlsConnector {
     /// provided here to perform the client half of a connection to a
     /// TLS-powered server.
     pub fn connect<'a, S>(&'a self, domain: &'a str, stream: S) -> PendingTlsStream<S>
-        where S: AsyncRead + AsyncWrite + Unpin,
+        where S: AsyncRead + AsyncWrite,
     {
-        PendingTlsStream::new(self.inner.connect(domain, stream.compat()))
+        PendingTlsStream::new(Role::Client, Some(domain), self.config.clone(), stream, |adapter| {
+            self.inner.connect(domain, adapter)
+        })
     }
-}
\ No newline at end of file
+
+    /// Resolves and connects to `addr`, given as `"host:port"`, then
+    /// performs the client half of a handshake with `host`.
+    ///
+    /// The host is resolved on a separate thread, and its IPv6 and IPv4
+    /// addresses are raced following RFC 8305 ("Happy Eyeballs"): attempts
+    /// alternate between address families and each new attempt starts once
+    /// the previous one fails or has been pending for 250ms. The timeout set
+    /// with `TlsConnectorBuilder::connect_timeout` covers resolution and the
+    /// TCP connection, but not the handshake.
+    ///
+    /// Racing and the connect timeout need the connector's `Timer`. Without
+    /// one, addresses are tried one after the other.
+    ///
+    /// IPv6 literals must be enclosed in brackets, as in `"[::1]:443"`.
+    /// Resolution and connection failures resolve to `Error::Connect`.
+    #[cfg(feature = "romio")]
+    pub async fn connect_to(&self, addr: &str) -> Result<TlsStream<TcpStream>, Error> {
+        let (host, port) = split_host_port(addr)
+            .ok_or_else(|| Error::InvalidAddress(addr.to_owned()))?;
+        let timeouts = &self.config.timeouts;
+        let stream = Dial::new(host, port, timeouts.timer.clone(), timeouts.connect).await
+            .map_err(Error::Connect)?;
+        let peer_addr = stream.peer_addr();
+        let pending = self.connect(host, stream);
+        if let Ok(peer_addr) = peer_addr {
+            pending.record_peer_addr(peer_addr);
+        }
+        pending.await
+    }
+}
+
+fn next_connector_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
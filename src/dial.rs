@@ -0,0 +1,307 @@
+use crate::timeout::{Delay, Timer};
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt, Poll};
+use romio::TcpStream;
+
+/// How long to wait for a connection attempt before racing the next
+/// address, as recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+type Attempt = BoxFuture<'static, io::Result<TcpStream>>;
+
+enum State {
+    Resolving(oneshot::Receiver<io::Result<Vec<SocketAddr>>>),
+    Connecting {
+        addrs: std::vec::IntoIter<SocketAddr>,
+        attempts: Vec<Attempt>,
+        next_attempt: Option<Delay>,
+        last_error: Option<io::Error>,
+    },
+    Done,
+}
+
+impl State {
+    fn connecting(addrs: Vec<SocketAddr>) -> State {
+        State::Connecting {
+            addrs: interleave(addrs).into_iter(),
+            attempts: Vec::new(),
+            next_attempt: None,
+            last_error: None,
+        }
+    }
+}
+
+/// Resolves a host name and connects to one of its addresses.
+///
+/// Resolution runs on a separate thread through the system resolver. The
+/// addresses are then tried following RFC 8305: address families alternate,
+/// starting with the family the resolver preferred, and a new attempt is
+/// started whenever the previous one fails or has been pending for
+/// `CONNECTION_ATTEMPT_DELAY`. The first connection established wins and
+/// the other attempts are dropped.
+///
+/// Without a `Timer`, attempts are made one after the other and no timeout
+/// is enforced.
+pub(crate) struct Dial {
+    state: State,
+    timer: Option<Arc<dyn Timer>>,
+    deadline: Option<Delay>,
+}
+
+impl Dial {
+    pub(crate) fn new(host: &str, port: u16, timer: Option<Arc<dyn Timer>>, timeout: Option<Duration>) -> Dial {
+        // IP literals need no resolver thread.
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Dial::from_addrs(vec![SocketAddr::new(ip, port)], timer, timeout);
+        }
+        let (tx, rx) = oneshot::channel();
+        let host = host.to_owned();
+        thread::spawn(move || {
+            let res = (host.as_str(), port).to_socket_addrs().map(|addrs| addrs.collect());
+            drop(tx.send(res));
+        });
+        Dial::with_state(State::Resolving(rx), timer, timeout)
+    }
+
+    /// Connects to one of `addrs`, which are tried as if the resolver had
+    /// returned them.
+    pub(crate) fn from_addrs(addrs: Vec<SocketAddr>, timer: Option<Arc<dyn Timer>>,
+                             timeout: Option<Duration>) -> Dial {
+        let mut dial = Dial::with_state(State::connecting(addrs), timer, timeout);
+        dial.start_attempt();
+        dial
+    }
+
+    fn with_state(state: State, timer: Option<Arc<dyn Timer>>, timeout: Option<Duration>) -> Dial {
+        let deadline = match (&timer, timeout) {
+            (Some(timer), Some(timeout)) => Some(timer.delay(timeout)),
+            _ => None,
+        };
+        Dial {
+            state,
+            timer,
+            deadline,
+        }
+    }
+
+    fn start_attempt(&mut self) {
+        let timer = self.timer.clone();
+        if let State::Connecting { ref mut addrs, ref mut attempts, ref mut next_attempt, .. } = self.state {
+            *next_attempt = None;
+            if let Some(addr) = addrs.next() {
+                attempts.push(TcpStream::connect(&addr).boxed());
+                if addrs.len() > 0 {
+                    *next_attempt = timer.map(|timer| timer.delay(CONNECTION_ATTEMPT_DELAY));
+                }
+            }
+        }
+    }
+}
+
+impl Future for Dial {
+    type Output = io::Result<TcpStream>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if let Some(ref mut deadline) = this.deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                this.state = State::Done;
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out")));
+            }
+        }
+
+        if let State::Resolving(ref mut rx) = this.state {
+            let addrs = match rx.poll_unpin(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(Ok(addrs))) => addrs,
+                Poll::Ready(Ok(Err(e))) => return Poll::Ready(Err(e)),
+                Poll::Ready(Err(oneshot::Canceled)) => {
+                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, "resolver thread panicked")))
+                }
+            };
+            this.state = State::connecting(addrs);
+            this.start_attempt();
+        }
+
+        loop {
+            let res = match this.state {
+                State::Connecting { ref addrs, ref mut attempts, ref mut next_attempt, ref mut last_error } => {
+                    poll_attempts(cx, addrs.len(), attempts, next_attempt, last_error)
+                }
+                _ => panic!("Dial polled after completion"),
+            };
+            match res {
+                Progress::Done(res) => {
+                    this.state = State::Done;
+                    return Poll::Ready(res);
+                }
+                Progress::StartNext => this.start_attempt(),
+                Progress::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+enum Progress {
+    Done(io::Result<TcpStream>),
+    StartNext,
+    Pending,
+}
+
+/// Polls the running connection attempts, reporting whether the dial is
+/// done or the next address should be tried.
+fn poll_attempts(cx: &mut Context<'_>, remaining: usize, attempts: &mut Vec<Attempt>,
+                 next_attempt: &mut Option<Delay>, last_error: &mut Option<io::Error>) -> Progress {
+    let mut start_next = false;
+    let mut i = 0;
+    while i < attempts.len() {
+        match attempts[i].as_mut().poll(cx) {
+            Poll::Pending => i += 1,
+            Poll::Ready(Ok(stream)) => return Progress::Done(Ok(stream)),
+            Poll::Ready(Err(e)) => {
+                drop(attempts.swap_remove(i));
+                *last_error = Some(e);
+                start_next = true;
+            }
+        }
+    }
+    if let Some(delay) = next_attempt.as_mut() {
+        if delay.as_mut().poll(cx).is_ready() {
+            start_next = true;
+        }
+    }
+    if remaining == 0 {
+        if attempts.is_empty() {
+            return Progress::Done(Err(last_error.take().unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "host name resolved to no addresses")
+            })));
+        }
+        return Progress::Pending;
+    }
+    if start_next {
+        Progress::StartNext
+    } else {
+        Progress::Pending
+    }
+}
+
+/// Orders addresses so that families alternate, starting with the family of
+/// the first address and otherwise keeping the resolver's order.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let preferred_v6 = match addrs.first() {
+        Some(addr) => addr.is_ipv6(),
+        None => return addrs,
+    };
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|addr| addr.is_ipv6() == preferred_v6);
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    let mut res = Vec::new();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return res,
+            (a, b) => res.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Splits `"host:port"` into its parts, removing the brackets around an IPv6
+/// literal such as `"[::1]:443"`.
+pub(crate) fn split_host_port(addr: &str) -> Option<(&str, u16)> {
+    let colon = addr.rfind(':')?;
+    let port = addr[colon + 1..].parse().ok()?;
+    let host = &addr[..colon];
+    let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else {
+        host
+    };
+    if host.is_empty() {
+        None
+    } else {
+        Some((host, port))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::block_on;
+    use romio::TcpListener;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    /// Returns an address on `ip` that nothing listens on.
+    fn closed_addr(ip: &str) -> SocketAddr {
+        let listener = TcpListener::bind(&addr(&format!("{}:0", ip))).unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[test]
+    fn interleave_alternates_families() {
+        let addrs = vec![addr("[::1]:1"), addr("[::2]:1"), addr("[::3]:1"), addr("127.0.0.1:1"), addr("127.0.0.2:1")];
+        assert_eq!(interleave(addrs), vec![
+            addr("[::1]:1"), addr("127.0.0.1:1"), addr("[::2]:1"), addr("127.0.0.2:1"), addr("[::3]:1"),
+        ]);
+
+        let addrs = vec![addr("127.0.0.1:1"), addr("127.0.0.2:1"), addr("[::1]:1")];
+        assert_eq!(interleave(addrs), vec![addr("127.0.0.1:1"), addr("[::1]:1"), addr("127.0.0.2:1")]);
+
+        assert_eq!(interleave(vec![]), vec![]);
+    }
+
+    #[test]
+    fn split_host_port_parses() {
+        assert_eq!(split_host_port("localhost:443"), Some(("localhost", 443)));
+        assert_eq!(split_host_port("127.0.0.1:8443"), Some(("127.0.0.1", 8443)));
+        assert_eq!(split_host_port("[::1]:443"), Some(("::1", 443)));
+        assert_eq!(split_host_port("localhost"), None);
+        assert_eq!(split_host_port("localhost:https"), None);
+        assert_eq!(split_host_port("localhost:65536"), None);
+        assert_eq!(split_host_port(":443"), None);
+        assert_eq!(split_host_port("[]:443"), None);
+    }
+
+    #[test]
+    fn falls_back_to_listening_address() {
+        // 127.0.0.0/8 is loopback, so each address below is a separate
+        // loopback address; only the last one has a listener.
+        let listener = TcpListener::bind(&addr("127.0.0.3:0")).unwrap();
+        let open = listener.local_addr().unwrap();
+        let addrs = vec![closed_addr("127.0.0.1"), closed_addr("127.0.0.2"), open];
+
+        let stream = block_on(Dial::from_addrs(addrs, None, None)).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), open);
+    }
+
+    #[test]
+    fn falls_back_across_families() {
+        // Whether or not IPv6 is available, connecting to ::1 fails and the
+        // IPv4 listener must be tried next.
+        let listener = TcpListener::bind(&addr("127.0.0.1:0")).unwrap();
+        let open = listener.local_addr().unwrap();
+        let addrs = vec![SocketAddr::new(addr("[::1]:0").ip(), open.port()), open];
+
+        let stream = block_on(Dial::from_addrs(addrs, None, None)).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), open);
+    }
+
+    #[test]
+    fn reports_last_error() {
+        let addrs = vec![closed_addr("127.0.0.1"), closed_addr("127.0.0.2")];
+        let err = block_on(Dial::from_addrs(addrs, None, None)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -12,6 +12,24 @@ pub enum Error {
     Native(#[cause] native_tls::Error),
     #[fail(display="Cannot repeat handshake")]
     RepeatedHandshake,
+    #[fail(display="Handshake timed out")]
+    HandshakeTimeout,
+    #[fail(display="Peer was not authorized: {}", _0)]
+    Unauthorized(String),
+    #[fail(display="Invalid address: {}", _0)]
+    InvalidAddress(String),
+    #[fail(display="Could not connect")]
+    Connect(#[cause] std::io::Error),
+    #[fail(display="Proxy error: {}", _0)]
+    Proxy(String),
+    #[fail(display="Peer certificate has been revoked")]
+    Revoked,
+    #[fail(display="Could not check peer certificate for revocation: {}", _0)]
+    RevocationUnavailable(String),
+    #[fail(display="Invalid certificate revocation list: {}", _0)]
+    InvalidCrl(String),
+    #[fail(display="Could not generate test certificate: {}", _0)]
+    TestCertificate(String),
 }
 
 unsafe impl Sync for Error {}
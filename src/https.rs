@@ -0,0 +1,268 @@
+use crate::errors::Error;
+use crate::pending::PendingTlsStream;
+use crate::{TlsAcceptor, TlsConnector, TlsStream};
+
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::task::Context;
+
+use failure::Fail;
+use futures::compat::{AsyncRead01CompatExt, Compat, Compat01As03, Stream01CompatExt};
+use futures::io::AsyncReadExt;
+use futures::stream::{FuturesUnordered, Stream, TryStreamExt};
+use futures::{FutureExt, Poll, TryFutureExt};
+use futures01::{Future as Future01, Stream as Stream01};
+use hyper::client::connect::{Connect, Connected, Destination};
+use hyper::client::HttpConnector;
+use log::debug;
+use tokio_io::{AsyncRead as AsyncRead01, AsyncWrite as AsyncWrite01};
+
+/// A TLS stream over a transport `T` implementing the tokio 0.1 I/O traits,
+/// itself implementing them as hyper expects.
+pub type HttpsStream<T> = Compat<TlsStream<Compat01As03<T>>>;
+
+/// A hyper `Connect` dialing `https` URIs over TLS and passing `http` URIs
+/// through.
+///
+/// The underlying connector `T`, hyper's `HttpConnector` by default,
+/// establishes the TCP connection. For `https` URIs the handshake is then
+/// performed with a `TlsConnector`, and a server selecting `h2` through
+/// ALPN is reported to hyper as supporting HTTP/2.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # extern crate hyper_crate as hyper;
+/// use hyper::Client;
+/// use tls_async::HttpsConnector;
+///
+/// let client = Client::builder().build::<_, hyper::Body>(HttpsConnector::new(4).unwrap());
+/// ```
+#[derive(Clone)]
+pub struct HttpsConnector<T> {
+    http: T,
+    tls: TlsConnector,
+}
+
+impl HttpsConnector<HttpConnector> {
+    /// Creates a connector using hyper's `HttpConnector`, with `threads`
+    /// threads for DNS resolution, and a `TlsConnector` with default
+    /// settings advertising `h2` and `http/1.1` through ALPN.
+    pub fn new(threads: usize) -> Result<Self, Error> {
+        let tls = TlsConnector::builder()
+            .request_alpns(&["h2", "http/1.1"])
+            .build()?;
+        let mut http = HttpConnector::new(threads);
+        http.enforce_http(false);
+        Ok(HttpsConnector::from((http, tls)))
+    }
+}
+
+impl<T> From<(T, TlsConnector)> for HttpsConnector<T> {
+    /// Combines a connector for the transport with a `TlsConnector`.
+    ///
+    /// `HttpConnector` must be told to accept `https` URIs with
+    /// `enforce_http(false)`. Advertising protocols through ALPN is left to
+    /// the `TlsConnector`'s configuration, see
+    /// `TlsConnectorBuilder::request_alpns`.
+    fn from((http, tls): (T, TlsConnector)) -> Self {
+        HttpsConnector {
+            http,
+            tls,
+        }
+    }
+}
+
+impl<T> Connect for HttpsConnector<T>
+    where T: Connect,
+          T::Transport: 'static,
+          T::Future: 'static,
+{
+    type Transport = MaybeHttpsStream<T::Transport>;
+    type Error = io::Error;
+    type Future = Box<dyn Future01<Item = (Self::Transport, Connected), Error = io::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let is_https = dst.scheme() == "https";
+        let host = dst.host().trim_start_matches('[').trim_end_matches(']').to_owned();
+        let connecting = self.http.connect(dst)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+        if !is_https {
+            return Box::new(connecting.map(|(stream, connected)| (MaybeHttpsStream::Http(stream), connected)));
+        }
+
+        let tls = self.tls.clone();
+        Box::new(connecting.and_then(move |(stream, connected)| {
+            let handshake = async move {
+                let stream = tls.connect(&host, stream.compat()).await.map_err(tls_error)?;
+                let connected = match stream.negotiated_alpn() {
+                    Ok(Some(ref protocol)) if protocol == b"h2" => connected.negotiated_h2(),
+                    _ => connected,
+                };
+                Ok((MaybeHttpsStream::Https(stream.compat()), connected))
+            };
+            handshake.boxed().compat()
+        }))
+    }
+}
+
+fn tls_error(e: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.compat())
+}
+
+/// A stream returned by `HttpsConnector`, either TLS or plain depending on
+/// the scheme of the URI.
+pub enum MaybeHttpsStream<T> {
+    /// A plain connection, for an `http` URI.
+    Http(T),
+    /// A TLS connection, for an `https` URI.
+    Https(HttpsStream<T>),
+}
+
+impl<T: AsyncRead01 + AsyncWrite01> Read for MaybeHttpsStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            MaybeHttpsStream::Http(stream) => stream.read(buf),
+            MaybeHttpsStream::Https(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl<T: AsyncRead01 + AsyncWrite01> Write for MaybeHttpsStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            MaybeHttpsStream::Http(stream) => stream.write(buf),
+            MaybeHttpsStream::Https(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            MaybeHttpsStream::Http(stream) => stream.flush(),
+            MaybeHttpsStream::Https(stream) => stream.flush(),
+        }
+    }
+}
+
+impl<T: AsyncRead01 + AsyncWrite01> AsyncRead01 for MaybeHttpsStream<T> {}
+
+impl<T: AsyncRead01 + AsyncWrite01> AsyncWrite01 for MaybeHttpsStream<T> {
+    fn shutdown(&mut self) -> futures01::Poll<(), io::Error> {
+        match self {
+            MaybeHttpsStream::Http(stream) => stream.shutdown(),
+            MaybeHttpsStream::Https(stream) => stream.shutdown(),
+        }
+    }
+}
+
+/// The default limit on the handshakes `HttpsIncoming` runs at once.
+const DEFAULT_MAX_PENDING_HANDSHAKES: usize = 64;
+
+/// Accepts TLS connections for hyper's server from a stream of incoming
+/// connections, such as tokio's `Incoming`.
+///
+/// Handshakes run concurrently, up to a limit after which no further
+/// connections are accepted until one completes. Connections whose handshake
+/// fails are logged and skipped. Configure a handshake timeout and a timer
+/// on the `TlsAcceptor` so that clients stalling their handshake cannot hold
+/// on to the slots indefinitely.
+///
+/// `native-tls` cannot select a protocol through ALPN on the server side, so
+/// clients will use HTTP/1.1 unless hyper is configured for HTTP/2 only.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # extern crate hyper_crate as hyper;
+/// use futures01::Future;
+/// use hyper::service::service_fn_ok;
+/// use hyper::{Body, Response, Server};
+/// use tls_async::{HttpsIncoming, TlsAcceptor};
+/// use tokio::net::TcpListener;
+///
+/// # fn f(acceptor: TlsAcceptor) {
+/// let listener = TcpListener::bind(&"127.0.0.1:443".parse().unwrap()).unwrap();
+/// let incoming = HttpsIncoming::new(acceptor, listener.incoming());
+/// let server = Server::builder(incoming)
+///     .serve(|| service_fn_ok(|_| Response::new(Body::from("hello"))))
+///     .map_err(|e| eprintln!("server error: {}", e));
+/// tokio::run(server);
+/// # }
+/// ```
+pub struct HttpsIncoming<I>
+    where I: Stream01,
+{
+    acceptor: TlsAcceptor,
+    // `None` once the incoming stream has ended.
+    incoming: Option<Compat01As03<I>>,
+    pending: FuturesUnordered<PendingTlsStream<Compat01As03<I::Item>>>,
+    max_pending: usize,
+}
+
+impl<I> HttpsIncoming<I>
+    where I: Stream01<Error = io::Error>,
+          I::Item: AsyncRead01 + AsyncWrite01,
+{
+    /// Performs a TLS handshake with `acceptor` on every connection yielded
+    /// by `incoming`.
+    pub fn new(acceptor: TlsAcceptor, incoming: I) -> Self {
+        HttpsIncoming {
+            acceptor,
+            incoming: Some(incoming.compat()),
+            pending: FuturesUnordered::new(),
+            max_pending: DEFAULT_MAX_PENDING_HANDSHAKES,
+        }
+    }
+
+    /// Sets how many handshakes may run at once.
+    ///
+    /// Defaults to 64.
+    pub fn set_max_pending_handshakes(&mut self, max: usize) {
+        assert!(max > 0, "max must be greater than zero");
+        self.max_pending = max;
+    }
+}
+
+impl<I> Stream01 for HttpsIncoming<I>
+    where I: Stream01<Error = io::Error>,
+          I::Item: AsyncRead01 + AsyncWrite01,
+{
+    type Item = HttpsStream<I::Item>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> futures01::Poll<Option<Self::Item>, io::Error> {
+        Compat::new(self).poll()
+    }
+}
+
+impl<I> Stream for HttpsIncoming<I>
+    where I: Stream01<Error = io::Error>,
+          I::Item: AsyncRead01 + AsyncWrite01,
+{
+    type Item = Result<HttpsStream<I::Item>, io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            while this.pending.len() < this.max_pending {
+                let incoming = match this.incoming {
+                    Some(ref mut incoming) => incoming,
+                    None => break,
+                };
+                match Pin::new(incoming).poll_next(cx) {
+                    Poll::Ready(Some(Ok(stream))) => this.pending.push(this.acceptor.accept(stream.compat())),
+                    Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                    Poll::Ready(None) => this.incoming = None,
+                    Poll::Pending => break,
+                }
+            }
+            match Pin::new(&mut this.pending).poll_next(cx) {
+                Poll::Ready(Some(Ok(stream))) => return Poll::Ready(Some(Ok(stream.compat()))),
+                // A slot was freed, so look for more connections.
+                Poll::Ready(Some(Err(e))) => debug!("TLS handshake with client failed: {}", e),
+                Poll::Ready(None) if this.incoming.is_none() => return Poll::Ready(None),
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -15,24 +15,73 @@
 //! built. Configuration of TLS parameters is still primarily done through the
 //! `native-tls` crate.
 #![feature(async_await)]
+#[cfg(feature = "hyper")]
+extern crate hyper_crate as hyper;
+
 mod acceptor;
+mod adapter;
+#[cfg(feature = "async-std")]
+pub mod async_std;
+mod authorizer;
+mod config;
 mod connector;
+#[cfg(feature = "romio")]
+mod dial;
 mod errors;
+#[cfg(feature = "hyper")]
+mod https;
+mod metrics;
 mod pending;
+mod pool;
+mod proxy;
+mod record_sizing;
+#[cfg(feature = "crl")]
+mod revocation;
+#[cfg(any(feature = "async-std", feature = "smol"))]
+mod runtime;
+#[cfg(feature = "smol")]
+pub mod smol;
+mod timeout;
+mod trace;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub use acceptor::TlsAcceptor as TlsAcceptor;
+pub use adapter::{RecoveredTransport, StdAdapter};
+pub use authorizer::{Authorizer, Principal};
 pub use connector::TlsConnector as TlsConnector;
 pub use errors::Error as Error;
+#[cfg(feature = "hyper")]
+pub use https::{HttpsConnector, HttpsIncoming, HttpsStream, MaybeHttpsStream};
+pub use metrics::{AtomicMetrics, Metrics, Role};
+pub use pending::PendingTlsStream;
+pub use pool::{Pool, PoolBuilder, Pooled};
+pub use proxy::Proxy;
+pub use record_sizing::RecordSizing;
+#[cfg(feature = "crl")]
+pub use revocation::{CertificateRevocationList, RevocationPolicy};
+#[cfg(any(feature = "async-std", feature = "smol"))]
+pub use runtime::FuturesIo;
+pub use timeout::{Delay, Timer};
+
+use crate::config::StreamConfig;
+use crate::metrics::SharedMetrics;
+use crate::timeout::{Direction, Timeouts};
+use crate::trace::ConnectionSpan;
 
 use std::io::{self, Read, Write};
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::Context;
+use std::time::Duration;
 
-use futures::compat::Compat;
-use futures::io::{AsyncRead, AsyncWrite};
-use futures::Poll;
+use futures::io::{AsyncRead, AsyncWrite, IoSlice, IoSliceMut};
+use futures::{ready, Poll};
 pub use native_tls::{Certificate as Certificate, Identity as Identity, Protocol as Protocol};
 
+/// The largest amount of plaintext carried by a single TLS record.
+const MAX_PLAINTEXT_LEN: usize = 16 * 1024;
+
 /// A wrapper around an underlying raw stream which implements the TLS or SSL
 /// protocol.
 ///
@@ -42,57 +91,274 @@ pub use native_tls::{Certificate as Certificate, Identity as Identity, Protocol
 /// to a `TlsStream` are encrypted when passing through to `S`.
 #[derive(Debug)]
 pub struct TlsStream<S> {
-    inner: native_tls::TlsStream<Compat<S>>,
+    inner: native_tls::TlsStream<StdAdapter<S>>,
+    role: Role,
+    principal: Option<Principal>,
+    metrics: Option<SharedMetrics>,
+    span: ConnectionSpan,
+    require_close_notify: bool,
+    peer_closed: bool,
+    write_shutdown: bool,
+    // An error hit by `poll_read_vectored` after it had already read data,
+    // returned by the next read.
+    read_error: Option<io::Error>,
+    // Scratch space for coalescing the slices passed to `poll_write_vectored`.
+    coalesce_buf: Vec<u8>,
+    record_sizing: RecordSizing,
+    // The amount of plaintext put into the next record when buffering.
+    record_size: usize,
+    write_buf: Vec<u8>,
+    timeouts: Timeouts,
 }
 
 impl<S> TlsStream<S> {
+    pub(crate) fn new(
+        inner: native_tls::TlsStream<StdAdapter<S>>,
+        role: Role,
+        principal: Option<Principal>,
+        span: ConnectionSpan,
+        config: &StreamConfig,
+    ) -> Self {
+        let record_sizing = config.record_sizing;
+        TlsStream {
+            inner,
+            role,
+            principal,
+            metrics: config.metrics.clone(),
+            span,
+            require_close_notify: config.require_close_notify,
+            peer_closed: false,
+            write_shutdown: false,
+            read_error: None,
+            coalesce_buf: Vec::new(),
+            record_sizing,
+            record_size: record_sizing.initial_record_size().unwrap_or(MAX_PLAINTEXT_LEN),
+            write_buf: Vec::new(),
+            timeouts: Timeouts::new(config.timeouts.clone()),
+        }
+    }
+
     /// Get access to the internal `native_tls::TlsStream` stream which also
     /// transitively allows access to `S`.
-    pub fn get_ref(&self) -> &native_tls::TlsStream<Compat<S>> {
+    ///
+    /// The stream is wrapped in a `StdAdapter`, whose `get_ref` returns `S`.
+    pub fn get_ref(&self) -> &native_tls::TlsStream<StdAdapter<S>> {
         &self.inner
     }
 
     /// Get mutable access to the internal `native_tls::TlsStream` stream which
     /// also transitively allows mutable access to `S`.
-    pub fn get_mut(&mut self) -> &mut native_tls::TlsStream<Compat<S>> {
+    ///
+    /// The stream is wrapped in a `StdAdapter`, whose `get_pin_mut` returns
+    /// `S`.
+    pub fn get_mut(&mut self) -> &mut native_tls::TlsStream<StdAdapter<S>> {
         &mut self.inner
     }
 
-    fn inner<'a>(self: Pin<&'a mut Self>) -> &'a mut native_tls::TlsStream<Compat<S>> {
-        unsafe {
-            &mut Pin::get_unchecked_mut(self).inner
-        }
+    /// Returns whether this is the client or the server side of the
+    /// connection.
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Returns the `tracing` span covering this connection, as created by the
+    /// `PendingTlsStream` it came from.
+    #[cfg(feature = "tracing")]
+    pub fn span(&self) -> &tracing::Span {
+        self.span.span()
+    }
+
+    /// Returns the `Principal` assigned to the peer by the acceptor's
+    /// `Authorizer`, if one was configured.
+    pub fn principal(&self) -> Option<&Principal> {
+        self.principal.as_ref()
+    }
+
+    /// Returns the protocol selected through ALPN, if any.
+    ///
+    /// Only clients can negotiate a protocol, see
+    /// `TlsConnectorBuilder::request_alpns`.
+    pub fn negotiated_alpn(&self) -> Result<Option<Vec<u8>>, Error> {
+        self.inner.negotiated_alpn().map_err(Error::Native)
+    }
+
+    /// Controls whether reaching EOF on the transport before the peer sent
+    /// `close_notify` makes `poll_read` fail with
+    /// `io::ErrorKind::UnexpectedEof`.
+    ///
+    /// Defaults to the setting of the connector or acceptor which created
+    /// this stream.
+    pub fn set_require_close_notify(&mut self, require_close_notify: bool) {
+        self.require_close_notify = require_close_notify;
+    }
+
+    /// Returns `true` if the transport was closed without the peer sending
+    /// `close_notify`, meaning any data read so far may be truncated.
+    pub fn is_truncated(&self) -> bool {
+        self.inner.get_ref().is_eof()
+    }
+
+    /// Returns `true` once a read has observed the peer's `close_notify`.
+    ///
+    /// The peer has then shut down its write side, but may still be reading
+    /// what we send until we shut down ours.
+    pub fn is_peer_closed(&self) -> bool {
+        self.peer_closed
+    }
+
+    /// Returns `true` once our `close_notify` has been sent, either by
+    /// `poll_shutdown_write` or `poll_close`.
+    pub fn is_write_shutdown(&self) -> bool {
+        self.write_shutdown
+    }
+
+    /// Returns `true` if plaintext written to this stream is still buffered
+    /// according to its `RecordSizing`, waiting for a flush.
+    pub(crate) fn has_buffered_plaintext(&self) -> bool {
+        !self.write_buf.is_empty()
+    }
+
+    /// Changes how data written to this stream is split into TLS records.
+    ///
+    /// Data already buffered is sent according to the new policy.
+    pub fn set_record_sizing(&mut self, record_sizing: RecordSizing) {
+        self.record_sizing = record_sizing;
+        self.record_size = record_sizing.initial_record_size().unwrap_or(MAX_PLAINTEXT_LEN);
+    }
+
+    /// Returns the idle timeout of this stream.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.timeouts.idle()
+    }
+
+    /// Sets how long this stream may go without completing any read or write
+    /// while an operation is pending, before failing it with
+    /// `io::ErrorKind::TimedOut`.
+    ///
+    /// A value of `None` disables the timeout.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeouts.set_idle(timeout);
+    }
+
+    /// Returns the read timeout of this stream.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.timeouts.read()
+    }
+
+    /// Sets how long a read may stay pending before failing with
+    /// `io::ErrorKind::TimedOut`.
+    ///
+    /// A value of `None` disables the timeout.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeouts.set_read(timeout);
+    }
+
+    /// Returns the write timeout of this stream.
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.timeouts.write()
+    }
+
+    /// Sets how long a write, flush or close may stay pending before failing
+    /// with `io::ErrorKind::TimedOut`.
+    ///
+    /// A value of `None` disables the timeout.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeouts.set_write(timeout);
+    }
+
+    /// Restarts the timeouts of this stream.
+    ///
+    /// The timeouts of a read or write start when it first has to wait, and
+    /// stop when it completes. The stream cannot tell when a pending read or
+    /// write is dropped instead, e.g. because it lost a `select`, so the next
+    /// one would carry on with its timeouts and fail straight away if they
+    /// have elapsed in the meantime. Call this after giving up on an
+    /// operation to give the next one timeouts of its own.
+    pub fn reset_timeouts(&mut self) {
+        self.timeouts.reset();
+    }
+
+    /// Sets the `Timer` used to enforce the timeouts of this stream.
+    ///
+    /// Timeouts have no effect until a timer is set, either here or on the
+    /// connector or acceptor which created this stream.
+    pub fn set_timer(&mut self, timer: Arc<dyn Timer>) {
+        self.timeouts.set_timer(timer);
     }
 }
 
-impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsStream<S> {
-    fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut [u8])
-                 -> Poll<Result<usize, io::Error>> {
-        match self.as_mut().inner().read(buf) {
-            Ok(sz) => Poll::Ready(Ok(sz)),
+impl<S: AsyncRead + AsyncWrite> TlsStream<S> {
+    /// Shuts down the write side of the connection by sending `close_notify`,
+    /// leaving the read side usable.
+    ///
+    /// This is the TLS equivalent of a TCP half-close: the peer observes the
+    /// end of our data while we can keep reading its response. The
+    /// underlying stream itself is left open; use `poll_close` to close both
+    /// the TLS session and the stream. Writing after the write side has been
+    /// shut down fails with `io::ErrorKind::BrokenPipe`.
+    ///
+    /// Calling this again after it completed is a no-op.
+    pub fn poll_shutdown_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let res = self.as_mut().poll_send_close_notify(cx);
+        timeout::apply(&mut self.timeouts, Direction::Write, cx, res)
+    }
+
+    fn poll_send_close_notify(mut self: Pin<&mut Self>, cx: &Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.inner.get_mut().set_waker(cx.waker());
+        if self.write_shutdown {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(self.as_mut().poll_drain(true))?;
+        match self.inner.shutdown().and_then(|()| self.inner.flush()) {
+            Ok(()) => {
+                self.span.event("sent close_notify");
+                self.write_shutdown = true;
+                Poll::Ready(Ok(()))
+            }
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                 Poll::Pending
             }
             Err(e) => Poll::Ready(Err(e))
         }
     }
-}
 
-impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
-    fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8])
-                  -> Poll<Result<usize, io::Error>> {
-        match self.as_mut().inner().write(buf) {
-            Ok(sz) => Poll::Ready(Ok(sz)),
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                Poll::Pending
+    /// Passes buffered plaintext to the TLS engine, one record at a time.
+    ///
+    /// Only full records are sent unless `all` is set, in which case the
+    /// remainder is sent as a shorter record.
+    fn poll_drain(mut self: Pin<&mut Self>, all: bool) -> Poll<Result<(), io::Error>> {
+        loop {
+            let this = &mut *self;
+            let len = this.write_buf.len();
+            if len == 0 || (!all && len < this.record_size) {
+                return Poll::Ready(Ok(()));
+            }
+            let sz = std::cmp::min(len, this.record_size);
+            match this.inner.write(&this.write_buf[..sz]) {
+                Ok(written) => {
+                    this.write_buf.drain(..written);
+                    if written == this.record_size {
+                        this.record_size = this.record_sizing.next_record_size(written);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Poll::Pending
+                }
+                Err(e) => return Poll::Ready(Err(e))
             }
-            Err(e) => Poll::Ready(Err(e))
         }
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        match self.as_mut().inner().flush() {
-            Ok(sz) => Poll::Ready(Ok(sz)),
+    /// Sends buffered plaintext and flushes the TLS engine.
+    fn poll_flush_plaintext(mut self: Pin<&mut Self>, cx: &Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.inner.get_mut().set_waker(cx.waker());
+        ready!(self.as_mut().poll_drain(true))?;
+        match self.inner.flush() {
+            Ok(()) => {
+                // The next message starts with small records again.
+                self.record_size = self.record_sizing.initial_record_size().unwrap_or(MAX_PLAINTEXT_LEN);
+                Poll::Ready(Ok(()))
+            }
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                 Poll::Pending
             }
@@ -100,13 +366,213 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
         }
     }
 
-    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        match self.as_mut().inner().shutdown() {
-            Ok(sz) => Poll::Ready(Ok(sz)),
+    /// Passes plaintext to the TLS engine, buffering it first according to
+    /// the `RecordSizing` policy.
+    fn poll_write_plaintext(mut self: Pin<&mut Self>, cx: &Context<'_>, buf: &[u8])
+                            -> Poll<Result<usize, io::Error>> {
+        self.inner.get_mut().set_waker(cx.waker());
+        if self.write_shutdown {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "TLS write side has been shut down",
+            )));
+        }
+        if self.record_sizing == RecordSizing::Unbuffered {
+            ready!(self.as_mut().poll_drain(true))?;
+            return match self.inner.write(buf) {
+                Ok(sz) => Poll::Ready(Ok(sz)),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    Poll::Pending
+                }
+                Err(e) => Poll::Ready(Err(e))
+            };
+        }
+
+        ready!(self.as_mut().poll_drain(false))?;
+        let record_size = self.record_size;
+        if self.write_buf.is_empty() && buf.len() >= record_size {
+            // A full record is available, so skip copying it into the buffer.
+            match self.inner.write(&buf[..record_size]) {
+                Ok(sz) => {
+                    if sz == record_size {
+                        self.record_size = self.record_sizing.next_record_size(sz);
+                    }
+                    Poll::Ready(Ok(sz))
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    Poll::Pending
+                }
+                Err(e) => Poll::Ready(Err(e))
+            }
+        } else {
+            let sz = std::cmp::min(buf.len(), record_size - self.write_buf.len());
+            self.write_buf.extend_from_slice(&buf[..sz]);
+            Poll::Ready(Ok(sz))
+        }
+    }
+
+    /// Reads plaintext from the TLS engine, telling a peer's `close_notify`
+    /// apart from a truncated connection.
+    pub(crate) fn poll_read_plaintext(mut self: Pin<&mut Self>, cx: &Context<'_>, buf: &mut [u8])
+                                      -> Poll<Result<usize, io::Error>> {
+        self.inner.get_mut().set_waker(cx.waker());
+        match self.inner.read(buf) {
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                 Poll::Pending
             }
+            // Depending on the backend, reaching EOF on the transport before
+            // `close_notify` is reported as a clean EOF or, e.g. by OpenSSL 3,
+            // as an unexpected EOF error. Any other error, such as a corrupt
+            // record, is passed on as is.
+            Ok(0) if !buf.is_empty() && self.is_truncated() => self.truncated(),
+            Err(ref e) if self.is_truncated() && is_unexpected_eof(e) => self.truncated(),
+            Ok(0) if !buf.is_empty() => {
+                if !self.peer_closed {
+                    self.span.event("received close_notify");
+                }
+                self.peer_closed = true;
+                Poll::Ready(Ok(0))
+            }
+            Ok(sz) => {
+                if let Some(ref metrics) = self.metrics {
+                    metrics.0.bytes_read(self.role, sz);
+                }
+                Poll::Ready(Ok(sz))
+            }
             Err(e) => Poll::Ready(Err(e))
         }
     }
 }
+
+impl<S> TlsStream<S> {
+    /// The outcome of a read which found the transport closed without
+    /// `close_notify`.
+    fn truncated(&self) -> Poll<Result<usize, io::Error>> {
+        if !self.require_close_notify {
+            return Poll::Ready(Ok(0));
+        }
+        self.span.event("transport closed without close_notify");
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "peer closed connection without sending TLS close_notify",
+        )))
+    }
+}
+
+/// Returns `true` if `e` is the backend reporting that the transport reached
+/// EOF in the middle of the TLS session.
+fn is_unexpected_eof(e: &io::Error) -> bool {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        return true;
+    }
+    #[cfg(all(not(target_os = "macos"), not(windows), not(target_os = "ios")))]
+    {
+        let stack = e.get_ref()
+            .and_then(|e| e.downcast_ref::<openssl::ssl::Error>())
+            .and_then(|e| e.ssl_error());
+        if let Some(stack) = stack {
+            return stack.errors().iter().any(|e| e.reason() == Some("unexpected eof while reading"));
+        }
+    }
+    false
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for TlsStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
+                 -> Poll<Result<usize, io::Error>> {
+        if let Some(e) = self.read_error.take() {
+            return Poll::Ready(Err(e));
+        }
+        let res = self.as_mut().poll_read_plaintext(cx, buf);
+        timeout::apply(&mut self.timeouts, Direction::Read, cx, res)
+    }
+
+    fn poll_read_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &mut [IoSliceMut<'_>])
+                          -> Poll<Result<usize, io::Error>> {
+        // Fill the slices in order for as long as decrypted data is available
+        // without blocking.
+        let mut total = 0;
+        for buf in bufs.iter_mut().filter(|buf| !buf.is_empty()) {
+            let len = buf.len();
+            match self.as_mut().poll_read(cx, buf) {
+                Poll::Ready(Ok(sz)) => {
+                    total += sz;
+                    if sz < len {
+                        break;
+                    }
+                }
+                // An error is kept for the next read, so that the data read
+                // so far is returned first.
+                Poll::Ready(Err(e)) if total > 0 => {
+                    self.read_error = Some(e);
+                    break;
+                }
+                // The pending read started the timeouts, but this call
+                // completes with the data read so far.
+                Poll::Pending if total > 0 => {
+                    self.timeouts.progress(Direction::Read);
+                    break;
+                }
+                other => return other,
+            }
+        }
+        Poll::Ready(Ok(total))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for TlsStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+                  -> Poll<Result<usize, io::Error>> {
+        let res = self.as_mut().poll_write_plaintext(cx, buf);
+        if let Poll::Ready(Ok(sz)) = res {
+            if let Some(ref metrics) = self.metrics {
+                metrics.0.bytes_written(self.role, sz);
+            }
+        }
+        timeout::apply(&mut self.timeouts, Direction::Write, cx, res)
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>])
+                           -> Poll<Result<usize, io::Error>> {
+        let mut bufs = bufs.iter().filter(|buf| !buf.is_empty());
+        let first = match bufs.next() {
+            Some(first) => first,
+            None => return Poll::Ready(Ok(0)),
+        };
+        if first.len() >= MAX_PLAINTEXT_LEN {
+            return self.poll_write(cx, first);
+        }
+
+        // Coalesce small slices, such as a header followed by a body, so they
+        // are sent in as few records as possible rather than one per slice.
+        let mut coalesced = std::mem::replace(&mut self.coalesce_buf, Vec::new());
+        coalesced.clear();
+        for buf in std::iter::once(first).chain(bufs) {
+            let sz = std::cmp::min(buf.len(), MAX_PLAINTEXT_LEN - coalesced.len());
+            coalesced.extend_from_slice(&buf[..sz]);
+            if coalesced.len() == MAX_PLAINTEXT_LEN {
+                break;
+            }
+        }
+        let res = self.as_mut().poll_write(cx, &coalesced);
+        self.coalesce_buf = coalesced;
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let res = self.as_mut().poll_flush_plaintext(cx);
+        timeout::apply(&mut self.timeouts, Direction::Write, cx, res)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        match self.as_mut().poll_shutdown_write(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        let res = self.inner.get_mut().get_pin_mut().poll_close(cx);
+        if let Poll::Ready(_) = res {
+            self.span.event("closed transport");
+        }
+        timeout::apply(&mut self.timeouts, Direction::Write, cx, res)
+    }
+}
@@ -0,0 +1,165 @@
+use crate::errors::Error;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Which side of a connection a `TlsStream` is on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// The stream was created by a `TlsConnector`.
+    Client,
+    /// The stream was created by a `TlsAcceptor`.
+    Server,
+}
+
+/// Receives events from the handshakes and streams of a `TlsConnector` or
+/// `TlsAcceptor`.
+///
+/// An observer is installed with `TlsConnectorBuilder::metrics` or
+/// `TlsAcceptorBuilder::metrics`. All methods default to doing nothing.
+/// They are called from inside `poll`, so they should return quickly.
+///
+/// `native-tls` does not report whether a session was resumed, so there is no
+/// event for resumption.
+pub trait Metrics: Send + Sync {
+    /// Called when a handshake starts.
+    fn handshake_started(&self, _role: Role) {}
+
+    /// Called when a handshake completes successfully, including
+    /// authorization by the acceptor's `Authorizer`.
+    fn handshake_succeeded(&self, _role: Role, _duration: Duration) {}
+
+    /// Called when a handshake fails.
+    ///
+    /// Match on `error` to tell e.g. certificate verification failures
+    /// (`Error::Handshake`) from rejections by an `Authorizer`
+    /// (`Error::Unauthorized`).
+    fn handshake_failed(&self, _role: Role, _duration: Duration, _error: &Error) {}
+
+    /// Called when a `PendingTlsStream` is dropped before its handshake
+    /// succeeded or failed.
+    ///
+    /// Every started handshake ends up reported exactly once as succeeded,
+    /// failed or cancelled.
+    fn handshake_cancelled(&self, _role: Role, _duration: Duration) {}
+
+    /// Called when plaintext is read from a `TlsStream`.
+    fn bytes_read(&self, _role: Role, _bytes: usize) {}
+
+    /// Called when plaintext is written to a `TlsStream`.
+    fn bytes_written(&self, _role: Role, _bytes: usize) {}
+}
+
+/// A `Metrics` implementation keeping running totals in atomic counters.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::sync::Arc;
+/// use tls_async::{AtomicMetrics, TlsConnector};
+///
+/// let metrics = Arc::new(AtomicMetrics::new());
+/// let connector = TlsConnector::builder()
+///     .metrics(metrics.clone())
+///     .build()
+///     .unwrap();
+/// // ... use the connector, then periodically export the totals:
+/// println!("{} handshakes failed", metrics.handshakes_failed());
+/// ```
+#[derive(Debug, Default)]
+pub struct AtomicMetrics {
+    handshakes_started: AtomicU64,
+    handshakes_succeeded: AtomicU64,
+    handshakes_failed: AtomicU64,
+    handshakes_cancelled: AtomicU64,
+    handshake_nanos: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl AtomicMetrics {
+    /// Creates a new set of counters, all starting at zero.
+    pub fn new() -> AtomicMetrics {
+        AtomicMetrics::default()
+    }
+
+    /// The number of handshakes started.
+    pub fn handshakes_started(&self) -> u64 {
+        self.handshakes_started.load(Ordering::Relaxed)
+    }
+
+    /// The number of handshakes completed successfully.
+    pub fn handshakes_succeeded(&self) -> u64 {
+        self.handshakes_succeeded.load(Ordering::Relaxed)
+    }
+
+    /// The number of handshakes which failed.
+    pub fn handshakes_failed(&self) -> u64 {
+        self.handshakes_failed.load(Ordering::Relaxed)
+    }
+
+    /// The number of handshakes dropped before they finished.
+    pub fn handshakes_cancelled(&self) -> u64 {
+        self.handshakes_cancelled.load(Ordering::Relaxed)
+    }
+
+    /// The total time spent in finished handshakes, successful or not.
+    pub fn handshake_time(&self) -> Duration {
+        Duration::from_nanos(self.handshake_nanos.load(Ordering::Relaxed))
+    }
+
+    /// The number of plaintext bytes read.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// The number of plaintext bytes written.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    fn add_handshake_time(&self, duration: Duration) {
+        let nanos = duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos());
+        self.handshake_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+impl Metrics for AtomicMetrics {
+    fn handshake_started(&self, _role: Role) {
+        self.handshakes_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handshake_succeeded(&self, _role: Role, duration: Duration) {
+        self.handshakes_succeeded.fetch_add(1, Ordering::Relaxed);
+        self.add_handshake_time(duration);
+    }
+
+    fn handshake_failed(&self, _role: Role, duration: Duration, _error: &Error) {
+        self.handshakes_failed.fetch_add(1, Ordering::Relaxed);
+        self.add_handshake_time(duration);
+    }
+
+    fn handshake_cancelled(&self, _role: Role, _duration: Duration) {
+        self.handshakes_cancelled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn bytes_read(&self, _role: Role, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn bytes_written(&self, _role: Role, bytes: usize) {
+        self.bytes_written.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// A `Metrics` observer which can be stored in types deriving `Debug`.
+#[derive(Clone)]
+pub(crate) struct SharedMetrics(pub(crate) Arc<dyn Metrics>);
+
+impl fmt::Debug for SharedMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SharedMetrics").finish()
+    }
+}
@@ -1,11 +1,19 @@
+use crate::adapter::{RecoveredTransport, Recovery};
+use crate::config::StreamConfig;
 use crate::errors::Error;
-use crate::TlsStream;
+use crate::metrics::Role;
+use crate::timeout::Delay;
+use crate::trace::ConnectionSpan;
+use crate::{StdAdapter, TlsStream};
 
+#[cfg(feature = "romio")]
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::Context;
+use std::time::Instant;
 
 use futures::Future;
-use futures::compat::Compat;
 use futures::io::{AsyncRead, AsyncWrite};
 use futures::Poll;
 use log::debug;
@@ -14,8 +22,8 @@ pub use native_tls::{TlsConnector as NativeTlsConnector, TlsStream as NativeTlsS
 
 enum Handshake<S> {
     Error(Error),
-    Midhandshake(MidHandshakeTlsStream<Compat<S>>),
-    Completed(NativeTlsStream<Compat<S>>),
+    Midhandshake(MidHandshakeTlsStream<StdAdapter<S>>),
+    Completed(NativeTlsStream<StdAdapter<S>>),
 }
 
 impl<S> Handshake<S> {
@@ -28,7 +36,7 @@ impl<S> Handshake<S> {
     }
 }
 
-type NativeHandshake<S> = Result<NativeTlsStream<Compat<S>>, HandshakeError<Compat<S>>>;
+type NativeHandshake<S> = Result<NativeTlsStream<StdAdapter<S>>, HandshakeError<StdAdapter<S>>>;
 
 impl<S> From<NativeHandshake<S>> for Handshake<S> {
     fn from(v: NativeHandshake<S>) -> Self {
@@ -42,41 +50,199 @@ impl<S> From<NativeHandshake<S>> for Handshake<S> {
 
 pub struct PendingTlsStream<S> {
     inner: Handshake<S>,
+    role: Role,
+    started: Instant,
+    span: ConnectionSpan,
+    config: StreamConfig,
+    recovery: Option<Arc<Recovery<S>>>,
+    deadline: Option<Delay>,
+    // Whether the outcome of the handshake was reported by `finish`.
+    finished: bool,
 }
 
 impl<S> PendingTlsStream<S> {
-    pub fn new(inner: NativeHandshake<S>) -> Self {
+    /// Starts a handshake over `stream` by calling `handshake`, which
+    /// performs its first step through `native-tls`.
+    ///
+    /// `sni` is the server name requested by a client, if any.
+    pub(crate) fn new<F>(role: Role, sni: Option<&str>, config: StreamConfig, stream: S, handshake: F) -> Self
+        where F: FnOnce(StdAdapter<S>) -> NativeHandshake<S>,
+    {
+        if let Some(ref metrics) = config.metrics {
+            metrics.0.handshake_started(role);
+        }
+        let span = ConnectionSpan::new(role, sni);
+        let started = Instant::now();
+        let recovery = if config.recover_transport {
+            Some(Arc::new(Recovery::new()))
+        } else {
+            None
+        };
+        let deadline = config.timeouts.delay(config.timeouts.handshake);
+        // There is no task yet to wake, so a first step which cannot complete
+        // is simply repeated by the first poll, with the task's waker.
+        let adapter = StdAdapter::new(stream, recovery.clone());
+        let inner = span.in_scope(|| Handshake::from(handshake(adapter)));
+        span.handshake_step(inner.was_pending());
         PendingTlsStream {
-            inner: Handshake::from(inner)
+            inner,
+            role,
+            started,
+            span,
+            config,
+            recovery,
+            deadline,
+            finished: false,
         }
     }
-    fn inner<'a>(self: Pin<&'a mut Self>) -> &'a mut Handshake<S> {
-        unsafe {
-            &mut Pin::get_unchecked_mut(self).inner
+
+    /// Takes back the transport of a handshake that failed, along with the
+    /// bytes read from it.
+    ///
+    /// This requires `recover_transport` to be enabled on the connector or
+    /// acceptor, and returns `None` otherwise or while the handshake has not
+    /// failed. It lets a server answer a client that does not speak TLS,
+    /// e.g. with a plaintext HTTP 400, or a client retry with another
+    /// configuration. To call it, poll the handshake through a reference:
+    ///
+    /// ```rust,no_run
+    /// # #![feature(async_await)]
+    /// # async fn f(acceptor: tls_async::TlsAcceptor, socket: romio::TcpStream) {
+    /// let mut pending = acceptor.accept(socket);
+    /// if let Err(_) = (&mut pending).await {
+    ///     if let Some(transport) = pending.take_transport() {
+    ///         let (socket, bytes_read) = transport.into_inner();
+    ///         // ...
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn take_transport(&mut self) -> Option<RecoveredTransport<S>> {
+        self.recovery.as_ref().and_then(|recovery| recovery.take())
+    }
+
+    /// Returns the `tracing` span covering this connection.
+    ///
+    /// The span carries the `role` of the connection and, on clients, the
+    /// `sni` requested from the server. `native-tls` does not report the
+    /// name a client requested, so `sni` stays empty on servers.
+    ///
+    /// `peer.addr` is recorded by `TlsConnector::connect_to`, which knows
+    /// the socket it connected. Otherwise the stream may not be a socket,
+    /// so it is left empty and can be recorded by the caller.
+    #[cfg(feature = "tracing")]
+    pub fn span(&self) -> &tracing::Span {
+        self.span.span()
+    }
+
+    /// Records the address of the peer on the connection's span.
+    #[cfg(feature = "romio")]
+    pub(crate) fn record_peer_addr(&self, addr: SocketAddr) {
+        self.span.record_peer_addr(addr);
+    }
+
+    /// Checks the server certificate against the connector's CRLs, if any, and
+    /// runs the acceptor's `Authorizer`, if any, on a completed handshake.
+    fn complete(&self, native_stream: NativeTlsStream<StdAdapter<S>>) -> Result<TlsStream<S>, Error> {
+        #[cfg(feature = "crl")]
+        {
+            let peer_certificate = native_stream.peer_certificate().map_err(Error::Native)?;
+            if let Err(e) = self.config.revocation.check(peer_certificate.as_ref()) {
+                debug!("Connection was rejected by revocation check: {}", e);
+                return Err(e);
+            }
+        }
+        let principal = match self.config.authorizer {
+            Some(ref authorizer) => {
+                let peer_certificate = native_stream.peer_certificate().map_err(Error::Native)?;
+                match authorizer.authorize(peer_certificate.as_ref()) {
+                    Ok(principal) => Some(principal),
+                    Err(reason) => {
+                        debug!("Connection was rejected by authorizer: {}", reason);
+                        return Err(Error::Unauthorized(reason))
+                    }
+                }
+            }
+            None => None,
+        };
+        Ok(TlsStream::new(native_stream, self.role, principal, self.span.clone(), &self.config))
+    }
+
+    /// Reports the outcome of the handshake to the `Metrics` observer and the
+    /// connection's span.
+    fn finish(&mut self, res: Result<TlsStream<S>, Error>) -> Poll<Result<TlsStream<S>, Error>> {
+        self.finished = true;
+        let duration = self.started.elapsed();
+        if let (Ok(_), Some(recovery)) = (&res, &self.recovery) {
+            recovery.disarm();
+        }
+        match res {
+            Ok(_) => self.span.handshake_succeeded(duration),
+            Err(ref e) => self.span.handshake_failed(duration, e),
+        }
+        if let Some(ref metrics) = self.config.metrics {
+            match res {
+                Ok(_) => metrics.0.handshake_succeeded(self.role, duration),
+                Err(ref e) => metrics.0.handshake_failed(self.role, duration, e),
+            }
+        }
+        Poll::Ready(res)
+    }
+}
+
+impl<S> PendingTlsStream<S> {
+    /// Called while the handshake is pending, failing it once the handshake
+    /// timeout has elapsed.
+    fn poll_deadline(&mut self, cx: &mut Context<'_>) -> Poll<Result<TlsStream<S>, Error>> {
+        let elapsed = match self.deadline {
+            Some(ref mut deadline) => deadline.as_mut().poll(cx).is_ready(),
+            None => false,
+        };
+        if !elapsed {
+            return Poll::Pending;
+        }
+        self.deadline = None;
+        self.inner = Handshake::Error(Error::RepeatedHandshake);
+        self.finish(Err(Error::HandshakeTimeout))
+    }
+}
+
+impl<S> Drop for PendingTlsStream<S> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.span.event("handshake cancelled");
+        if let Some(ref metrics) = self.config.metrics {
+            metrics.0.handshake_cancelled(self.role, self.started.elapsed());
         }
     }
 }
 
-impl<S: AsyncRead + AsyncWrite + std::fmt::Debug + Unpin> Future for PendingTlsStream<S> {
+impl<S: AsyncRead + AsyncWrite> Future for PendingTlsStream<S> {
     type Output = Result<TlsStream<S>, Error>;
 
-    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         loop {
-            let handshake = std::mem::replace(self.as_mut().inner(), Handshake::Error(Error::RepeatedHandshake));
+            let handshake = std::mem::replace(&mut self.inner, Handshake::Error(Error::RepeatedHandshake));
             match handshake {
-                Handshake::Error(e) => return Poll::Ready(Err(e)),
-                Handshake::Midhandshake(midhandshake_stream) => {
+                Handshake::Error(Error::RepeatedHandshake) => return Poll::Ready(Err(Error::RepeatedHandshake)),
+                Handshake::Error(e) => return self.finish(Err(e)),
+                Handshake::Midhandshake(mut midhandshake_stream) => {
                     debug!("Connection was interrupted mid handshake, attempting handshake");
-                    let res = Handshake::from(midhandshake_stream.handshake());
+                    midhandshake_stream.get_mut().set_waker(cx.waker());
+                    let res = self.span.in_scope(|| Handshake::from(midhandshake_stream.handshake()));
                     let was_pending = res.was_pending();
-                    *self.as_mut().inner() = res;
+                    self.span.handshake_step(was_pending);
+                    self.inner = res;
                     if was_pending {
-                        return Poll::Pending;
+                        return self.poll_deadline(cx);
                     }
                 }
                 Handshake::Completed(native_stream) => {
                     debug!("Connection was completed");
-                    return Poll::Ready(Ok(TlsStream { inner: native_stream }))
+                    let res = self.complete(native_stream);
+                    return self.finish(res)
                 }
             }
         }
@@ -0,0 +1,568 @@
+use crate::errors::Error;
+use crate::{TlsConnector, TlsStream};
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Waker};
+use std::time::{Duration, Instant};
+
+use futures::future::poll_fn;
+use futures::io::{AsyncRead, AsyncWrite, IoSlice, IoSliceMut};
+use futures::task::noop_waker_ref;
+use futures::{Future, Poll};
+use log::debug;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    host: String,
+    port: u16,
+    connector: u64,
+}
+
+struct Idle<S> {
+    stream: TlsStream<S>,
+    since: Instant,
+}
+
+struct State<S> {
+    idle: HashMap<Key, VecDeque<Idle<S>>>,
+    idle_count: usize,
+    // Connections counted against the limits, whether idle, in use or being
+    // established.
+    open: HashMap<Key, usize>,
+    open_count: usize,
+    // Tasks waiting in `get` for a connection to be returned or closed.
+    waiters: Vec<Waker>,
+}
+
+struct Shared<S> {
+    state: Mutex<State<S>>,
+    max_idle_per_host: usize,
+    max_idle: usize,
+    max_connections_per_host: Option<usize>,
+    max_connections: Option<usize>,
+    idle_timeout: Option<Duration>,
+}
+
+/// A builder for `Pool`s.
+pub struct PoolBuilder<S> {
+    max_idle_per_host: usize,
+    max_idle: usize,
+    max_connections_per_host: Option<usize>,
+    max_connections: Option<usize>,
+    idle_timeout: Option<Duration>,
+    _marker: PhantomData<fn() -> S>,
+}
+
+impl<S> PoolBuilder<S> {
+    /// Sets how many idle connections are kept for each host, port and
+    /// connector.
+    ///
+    /// Defaults to 8.
+    pub fn max_idle_per_host(&mut self, max: usize) -> &mut PoolBuilder<S> {
+        self.max_idle_per_host = max;
+        self
+    }
+
+    /// Sets how many idle connections are kept in total. When the limit is
+    /// reached, the connection idle for the longest time is closed.
+    ///
+    /// Defaults to 64.
+    pub fn max_idle(&mut self, max: usize) -> &mut PoolBuilder<S> {
+        self.max_idle = max;
+        self
+    }
+
+    /// Sets how many connections may be open at once for each host, port and
+    /// connector, counting idle connections, those in use and those being
+    /// established. Once the limit is reached, `get` waits for one of them
+    /// to be returned or dropped.
+    ///
+    /// Defaults to `None`, for no limit.
+    pub fn max_connections_per_host(&mut self, max: Option<usize>) -> &mut PoolBuilder<S> {
+        self.max_connections_per_host = max;
+        self
+    }
+
+    /// Sets how many connections may be open at once in total. Once the
+    /// limit is reached, `get` closes the longest idle connection to another
+    /// host to make room, or waits for a connection to be returned or
+    /// dropped if none is idle.
+    ///
+    /// Defaults to `None`, for no limit.
+    pub fn max_connections(&mut self, max: Option<usize>) -> &mut PoolBuilder<S> {
+        self.max_connections = max;
+        self
+    }
+
+    /// Sets how long a connection may stay idle before it is closed instead
+    /// of reused.
+    ///
+    /// Defaults to 90 seconds. `None` keeps idle connections indefinitely.
+    pub fn idle_timeout(&mut self, timeout: Option<Duration>) -> &mut PoolBuilder<S> {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Creates a new `Pool`.
+    pub fn build(&self) -> Pool<S> {
+        Pool {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    idle: HashMap::new(),
+                    idle_count: 0,
+                    open: HashMap::new(),
+                    open_count: 0,
+                    waiters: Vec::new(),
+                }),
+                max_idle_per_host: self.max_idle_per_host,
+                max_idle: self.max_idle,
+                max_connections_per_host: self.max_connections_per_host,
+                max_connections: self.max_connections,
+                idle_timeout: self.idle_timeout,
+            }),
+        }
+    }
+}
+
+/// A pool of `TlsStream`s, reused for requests to the same host.
+///
+/// Connections are keyed by host, port and the `TlsConnector` which created
+/// them, so that a connection is only reused with the configuration it was
+/// established with. Clones of a connector share its configuration and
+/// hence its connections.
+///
+/// `get` hands out a `Pooled` stream, which goes back to the pool when
+/// dropped. It is only reused if it is still open: streams which were shut
+/// down or closed by the peer are dropped, and an idle stream is checked for
+/// a close or unexpected data from the peer before it is handed out again.
+/// Use `Pooled::discard` for a stream left in a state unsuitable for another
+/// request, e.g. with a response only partially read. A stream returned with
+/// plaintext still buffered by its `RecordSizing` is not reused either.
+///
+/// The number of open connections can be limited per host and in total, in
+/// which case `get` waits for a connection to become available. Connections
+/// which expire or are evicted to stay within the limits are shut down
+/// cleanly as soon as they are, with a single attempt at sending
+/// `close_notify` which does not wait for the transport.
+///
+/// When no idle connection is available, `get` establishes a fresh one with
+/// the same connector, so any session cache kept by the TLS backend for that
+/// connector can be used to resume a previous session.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// #![feature(async_await)]
+/// use romio::TcpStream;
+/// use tls_async::{Pool, TlsConnector};
+///
+/// # futures::executor::block_on(async {
+/// let connector = TlsConnector::new().unwrap();
+/// let pool = Pool::new();
+///
+/// let stream = pool.get(&connector, "example.com", 443, || async {
+///     let addr = "93.184.216.34:443".parse().unwrap();
+///     TcpStream::connect(&addr).await
+/// }).await.unwrap();
+/// # let _: tls_async::Pooled<TcpStream> = stream;
+/// # })
+/// ```
+pub struct Pool<S> {
+    shared: Arc<Shared<S>>,
+}
+
+impl<S> Pool<S> {
+    /// Returns a new pool with default settings.
+    pub fn new() -> Pool<S> {
+        Pool::builder().build()
+    }
+
+    /// Returns a new builder for a `Pool`.
+    pub fn builder() -> PoolBuilder<S> {
+        PoolBuilder {
+            max_idle_per_host: 8,
+            max_idle: 64,
+            max_connections_per_host: None,
+            max_connections: None,
+            idle_timeout: Some(Duration::from_secs(90)),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of idle connections in the pool.
+    pub fn idle_count(&self) -> usize {
+        self.shared.state.lock().unwrap().idle_count
+    }
+
+    /// Returns the number of open connections counted against the limits,
+    /// whether idle, in use or being established.
+    pub fn open_count(&self) -> usize {
+        self.shared.state.lock().unwrap().open_count
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Pool<S> {
+    /// Returns an idle connection to `host` and `port` established by
+    /// `connector`, or a new one.
+    ///
+    /// If no idle connection can be reused, `connect` is called to open a
+    /// transport to the host, over which `connector` then performs the
+    /// handshake. Failing to open the transport resolves to
+    /// `Error::Connect`.
+    ///
+    /// If a connection limit is reached, this waits until a connection is
+    /// returned to the pool or dropped.
+    pub async fn get<F, T>(&self, connector: &TlsConnector, host: &str, port: u16, connect: F)
+                           -> Result<Pooled<S>, Error>
+        where F: FnOnce() -> T,
+              T: Future<Output = io::Result<S>>,
+    {
+        let key = Key {
+            host: host.to_owned(),
+            port,
+            connector: connector.id(),
+        };
+        loop {
+            let idle = poll_fn(|cx| self.shared.poll_acquire(&key, cx)).await;
+            // From here on, `pooled` holds the connection's slot, and gives
+            // it back if dropped without a stream.
+            let mut pooled = Pooled {
+                reused: idle.is_some(),
+                stream: idle,
+                shared: self.shared.clone(),
+                key: key.clone(),
+            };
+            let stream = match pooled.stream {
+                Some(ref mut stream) => stream,
+                None => {
+                    let transport = connect().await.map_err(Error::Connect)?;
+                    pooled.stream = Some(connector.connect(host, transport).await?);
+                    return Ok(pooled);
+                }
+            };
+            // The candidate is probed without holding the lock, and is owned
+            // by this call from then on whether it is reused or dropped.
+            if poll_fn(|cx| Poll::Ready(is_alive(stream, cx))).await {
+                // The previous borrower may have given up on a pending
+                // operation.
+                stream.reset_timeouts();
+                return Ok(pooled);
+            }
+            debug!("Pooled connection to {}:{} was closed", key.host, key.port);
+            pooled.discard();
+        }
+    }
+}
+
+impl<S> State<S> {
+    fn reserve(&mut self, key: &Key) {
+        *self.open.entry(key.clone()).or_insert(0) += 1;
+        self.open_count += 1;
+    }
+
+    /// Gives back the slot of a connection which was closed or dropped.
+    fn release(&mut self, key: &Key) {
+        if let Some(open) = self.open.get_mut(key) {
+            *open -= 1;
+            if *open == 0 {
+                self.open.remove(key);
+            }
+        }
+        self.open_count -= 1;
+        self.wake();
+    }
+
+    fn wake(&mut self) {
+        for waker in self.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Takes the connection which has been idle for the longest time.
+    fn pop_oldest(&mut self) -> Option<(Key, TlsStream<S>)> {
+        let (_, oldest) = self.idle.iter()
+            .filter_map(|(key, idle)| idle.front().map(|idle| (idle.since, key.clone())))
+            .min()?;
+        let idle = self.idle.get_mut(&oldest).unwrap();
+        let stream = idle.pop_front().unwrap().stream;
+        if idle.is_empty() {
+            self.idle.remove(&oldest);
+        }
+        self.idle_count -= 1;
+        Some((oldest, stream))
+    }
+}
+
+impl<S> Shared<S> {
+    fn is_expired(&self, since: Instant) -> bool {
+        self.idle_timeout.map_or(false, |timeout| since.elapsed() >= timeout)
+    }
+
+    fn has_room(&self, state: &State<S>) -> bool {
+        self.max_connections.map_or(true, |max| state.open_count < max)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Shared<S> {
+    /// Takes the most recently used idle connection for `key` which has not
+    /// expired, or reserves a slot for a new one, resolving to `None`.
+    fn poll_acquire(&self, key: &Key, cx: &mut Context<'_>) -> Poll<Option<TlsStream<S>>> {
+        let mut evicted = Vec::new();
+        let res = self.acquire(&mut self.state.lock().unwrap(), key, cx, &mut evicted);
+        evicted.into_iter().for_each(close);
+        res
+    }
+
+    fn acquire(&self, state: &mut State<S>, key: &Key, cx: &mut Context<'_>, evicted: &mut Vec<TlsStream<S>>)
+               -> Poll<Option<TlsStream<S>>> {
+        while let Some(Idle { stream, since }) = state.idle.get_mut(key).and_then(VecDeque::pop_back) {
+            state.idle_count -= 1;
+            if state.idle.get(key).map_or(false, VecDeque::is_empty) {
+                state.idle.remove(key);
+            }
+            if self.is_expired(since) {
+                debug!("Pooled connection to {}:{} expired", key.host, key.port);
+                state.release(key);
+                evicted.push(stream);
+                continue;
+            }
+            return Poll::Ready(Some(stream));
+        }
+
+        let open = state.open.get(key).cloned().unwrap_or(0);
+        if self.max_connections_per_host.map_or(true, |max| open < max) {
+            // No connection to this host is idle, so make room by closing
+            // one to another host.
+            if !self.has_room(state) {
+                if let Some((oldest, stream)) = state.pop_oldest() {
+                    state.release(&oldest);
+                    evicted.push(stream);
+                }
+            }
+            if self.has_room(state) {
+                state.reserve(key);
+                return Poll::Ready(None);
+            }
+        }
+        if !state.waiters.iter().any(|waker| waker.will_wake(cx.waker())) {
+            state.waiters.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    fn checkin(&self, key: Key, stream: TlsStream<S>) {
+        let mut evicted = Vec::new();
+        self.keep(&mut self.state.lock().unwrap(), key, stream, &mut evicted);
+        evicted.into_iter().for_each(close);
+    }
+
+    fn keep(&self, state: &mut State<S>, key: Key, stream: TlsStream<S>, evicted: &mut Vec<TlsStream<S>>) {
+        if stream.is_peer_closed() || stream.is_write_shutdown() || stream.is_truncated() {
+            state.release(&key);
+            return;
+        }
+        // Stale plaintext would be sent ahead of the next borrower's data.
+        if stream.has_buffered_plaintext() || self.max_idle_per_host == 0 || self.max_idle == 0 {
+            state.release(&key);
+            evicted.push(stream);
+            return;
+        }
+
+        // Evict expired connections and make room within the limits. Each
+        // queue is ordered from the longest idle connection to the most
+        // recently returned one.
+        let mut expired = Vec::new();
+        for (idle_key, idle) in state.idle.iter_mut() {
+            while idle.front().map_or(false, |idle| self.is_expired(idle.since)) {
+                expired.push(idle_key.clone());
+                evicted.push(idle.pop_front().unwrap().stream);
+                state.idle_count -= 1;
+            }
+        }
+        state.idle.retain(|_, idle| !idle.is_empty());
+        for idle_key in &expired {
+            state.release(idle_key);
+        }
+        if let Some(idle) = state.idle.get_mut(&key) {
+            if idle.len() >= self.max_idle_per_host {
+                evicted.push(idle.pop_front().unwrap().stream);
+                state.idle_count -= 1;
+                state.release(&key);
+            }
+        }
+        if state.idle_count >= self.max_idle {
+            if let Some((oldest, stream)) = state.pop_oldest() {
+                state.release(&oldest);
+                evicted.push(stream);
+            }
+        }
+
+        state.idle.entry(key).or_insert_with(VecDeque::new).push_back(Idle {
+            stream,
+            since: Instant::now(),
+        });
+        state.idle_count += 1;
+        // A task waiting for this host can reuse the connection, and one
+        // waiting for another host can close it to make room.
+        state.wake();
+    }
+}
+
+/// Makes a single attempt at shutting down an evicted stream cleanly,
+/// without waiting for the transport.
+fn close<S: AsyncRead + AsyncWrite>(mut stream: TlsStream<S>) {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    drop(Pin::new(&mut stream).poll_close(&mut cx));
+}
+
+/// Attempts a read on an idle stream without waiting. Nothing should arrive
+/// on an idle connection, so anything but `Pending` means it is unusable:
+/// the peer closed it, it failed, or it is out of sync with the protocol.
+///
+/// Like any read, this registers the task of `cx` for wake-ups of the
+/// stream, which is fine as long as that task owns it afterwards.
+fn is_alive<S: AsyncRead + AsyncWrite>(stream: &mut TlsStream<S>, cx: &Context<'_>) -> bool {
+    let mut buf = [0; 1];
+    match Pin::new(stream).poll_read_plaintext(cx, &mut buf) {
+        Poll::Pending => true,
+        Poll::Ready(_) => false,
+    }
+}
+
+impl<S> fmt::Debug for PoolBuilder<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolBuilder")
+            .field("max_idle_per_host", &self.max_idle_per_host)
+            .field("max_idle", &self.max_idle)
+            .field("max_connections_per_host", &self.max_connections_per_host)
+            .field("max_connections", &self.max_connections)
+            .field("idle_timeout", &self.idle_timeout)
+            .finish()
+    }
+}
+
+impl<S> Default for Pool<S> {
+    fn default() -> Self {
+        Pool::new()
+    }
+}
+
+impl<S> Clone for Pool<S> {
+    fn clone(&self) -> Self {
+        Pool {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for Pool<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field("idle_count", &self.idle_count())
+            .field("open_count", &self.open_count())
+            .field("max_idle_per_host", &self.shared.max_idle_per_host)
+            .field("max_idle", &self.shared.max_idle)
+            .field("max_connections_per_host", &self.shared.max_connections_per_host)
+            .field("max_connections", &self.shared.max_connections)
+            .field("idle_timeout", &self.shared.idle_timeout)
+            .finish()
+    }
+}
+
+/// A `TlsStream` borrowed from a `Pool`, returned to it when dropped.
+pub struct Pooled<S: AsyncRead + AsyncWrite> {
+    // Only `None` while being established, or once discarded or taken.
+    stream: Option<TlsStream<S>>,
+    shared: Arc<Shared<S>>,
+    key: Key,
+    reused: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite> Pooled<S> {
+    /// Returns `true` if the stream was reused from the pool rather than
+    /// newly established.
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// Drops the stream instead of returning it to the pool.
+    pub fn discard(mut self) {
+        self.stream = None;
+    }
+
+    /// Takes the stream out of the pool for good.
+    pub fn into_inner(mut self) -> TlsStream<S> {
+        self.stream.take().expect("stream was taken")
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Deref for Pooled<S> {
+    type Target = TlsStream<S>;
+
+    fn deref(&self) -> &TlsStream<S> {
+        self.stream.as_ref().expect("stream was taken")
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> DerefMut for Pooled<S> {
+    fn deref_mut(&mut self) -> &mut TlsStream<S> {
+        self.stream.as_mut().expect("stream was taken")
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Drop for Pooled<S> {
+    fn drop(&mut self) {
+        match self.stream.take() {
+            Some(stream) => self.shared.checkin(self.key.clone(), stream),
+            None => self.shared.state.lock().unwrap().release(&self.key),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> fmt::Debug for Pooled<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pooled")
+            .field("host", &self.key.host)
+            .field("port", &self.key.port)
+            .field("reused", &self.reused)
+            .finish()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for Pooled<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
+                 -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut **self).poll_read(cx, buf)
+    }
+
+    fn poll_read_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &mut [IoSliceMut<'_>])
+                          -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut **self).poll_read_vectored(cx, bufs)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for Pooled<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+                  -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut **self).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>])
+                           -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut **self).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut **self).poll_close(cx)
+    }
+}
@@ -0,0 +1,282 @@
+#[cfg(feature = "romio")]
+use crate::dial::{split_host_port, Dial};
+use crate::errors::Error;
+
+use std::net::IpAddr;
+
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "romio")]
+use romio::TcpStream;
+
+/// The largest HTTP response header accepted from a proxy.
+const MAX_RESPONSE_LEN: usize = 8 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Http,
+    Socks5,
+}
+
+/// A proxy through which a tunnel to the TLS server is opened.
+///
+/// `connect` dials the proxy set with `address` over TCP, and `tunnel` runs
+/// the proxy protocol over a stream already connected to the proxy. Both
+/// return the stream once it carries bytes to and from the target, ready to
+/// be passed to `TlsConnector::connect`.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// #![feature(async_await)]
+/// use tls_async::{Proxy, TlsConnector};
+///
+/// # futures::executor::block_on(async {
+/// let stream = Proxy::http().address("proxy.example.com:3128").credentials("user", "secret")
+///     .connect("example.com", 443).await.unwrap();
+///
+/// let connector = TlsConnector::new().unwrap();
+/// let stream = connector.connect("example.com", stream).await.unwrap();
+/// # })
+/// ```
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    kind: Kind,
+    address: Option<String>,
+    credentials: Option<(String, String)>,
+}
+
+impl Proxy {
+    /// An HTTP proxy, tunnelling with the `CONNECT` method.
+    pub fn http() -> Proxy {
+        Proxy {
+            kind: Kind::Http,
+            address: None,
+            credentials: None,
+        }
+    }
+
+    /// A SOCKS5 proxy.
+    ///
+    /// The target host name is sent to the proxy as is, so it is resolved by
+    /// the proxy rather than locally.
+    pub fn socks5() -> Proxy {
+        Proxy {
+            kind: Kind::Socks5,
+            address: None,
+            credentials: None,
+        }
+    }
+
+    /// Sets the address of the proxy, given as `"host:port"`, which
+    /// `connect` dials.
+    ///
+    /// IPv6 literals must be enclosed in brackets, as in `"[::1]:3128"`.
+    pub fn address(&mut self, addr: &str) -> &mut Proxy {
+        self.address = Some(addr.to_owned());
+        self
+    }
+
+    /// Sets the credentials used to authenticate with the proxy.
+    ///
+    /// HTTP proxies receive them with basic authentication, SOCKS5 proxies
+    /// with username/password authentication (RFC 1929).
+    ///
+    /// Defaults to no credentials.
+    pub fn credentials(&mut self, username: &str, password: &str) -> &mut Proxy {
+        self.credentials = Some((username.to_owned(), password.to_owned()));
+        self
+    }
+
+    /// Connects to the proxy set with `address`, then asks it to open a
+    /// tunnel to `host` and `port`, returning the connection once the tunnel
+    /// is open.
+    ///
+    /// The proxy's host name is resolved locally and its addresses are tried
+    /// in turn, as by `TlsConnector::connect_to`. A missing or malformed
+    /// proxy address resolves to `Error::InvalidAddress`, failures to reach
+    /// the proxy to `Error::Connect`, and a proxy refusing the request or not
+    /// speaking the protocol to `Error::Proxy`.
+    #[cfg(feature = "romio")]
+    pub async fn connect(&self, host: &str, port: u16) -> Result<TcpStream, Error> {
+        let addr = self.address.as_ref().map_or("", String::as_str);
+        let (proxy_host, proxy_port) = split_host_port(addr)
+            .ok_or_else(|| Error::InvalidAddress(addr.to_owned()))?;
+        let stream = Dial::new(proxy_host, proxy_port, None, None).await
+            .map_err(Error::Connect)?;
+        self.tunnel(stream, host, port).await
+    }
+
+    /// Asks the proxy at the other end of `stream` to open a tunnel to
+    /// `host` and `port`, returning `stream` once it is open.
+    ///
+    /// Failures to reach the proxy resolve to `Error::Connect`; a proxy
+    /// refusing the request or not speaking the protocol to
+    /// `Error::Proxy`.
+    pub async fn tunnel<S>(&self, mut stream: S, host: &str, port: u16) -> Result<S, Error>
+        where S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match self.kind {
+            Kind::Http => self.http_connect(&mut stream, host, port).await?,
+            Kind::Socks5 => self.socks5_connect(&mut stream, host, port).await?,
+        }
+        Ok(stream)
+    }
+
+    async fn http_connect<S>(&self, stream: &mut S, host: &str, port: u16) -> Result<(), Error>
+        where S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let authority = if host.contains(':') {
+            format!("[{}]:{}", host, port)
+        } else {
+            format!("{}:{}", host, port)
+        };
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+        if let Some((ref username, ref password)) = self.credentials {
+            let token = base64(format!("{}:{}", username, password).as_bytes());
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await.map_err(Error::Connect)?;
+        stream.flush().await.map_err(Error::Connect)?;
+
+        // Read one byte at a time, so nothing after the header (i.e. the
+        // start of the TLS handshake) is consumed.
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() == MAX_RESPONSE_LEN {
+                return Err(Error::Proxy("response header too long".to_owned()));
+            }
+            let mut byte = [0];
+            stream.read_exact(&mut byte).await.map_err(Error::Connect)?;
+            response.push(byte[0]);
+        }
+
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response.lines().next().unwrap_or("");
+        let mut parts = status_line.splitn(3, ' ');
+        match (parts.next(), parts.next().and_then(|code| code.parse::<u16>().ok())) {
+            (Some(version), Some(code)) if version.starts_with("HTTP/1.") => {
+                if code / 100 == 2 {
+                    Ok(())
+                } else {
+                    Err(Error::Proxy(format!("CONNECT refused: {}", status_line)))
+                }
+            }
+            _ => Err(Error::Proxy(format!("invalid status line: {:?}", status_line))),
+        }
+    }
+
+    async fn socks5_connect<S>(&self, stream: &mut S, host: &str, port: u16) -> Result<(), Error>
+        where S: AsyncRead + AsyncWrite + Unpin,
+    {
+        // Method negotiation.
+        let greeting: &[u8] = match self.credentials {
+            Some(_) => &[5, 2, 0, 2],
+            None => &[5, 1, 0],
+        };
+        stream.write_all(greeting).await.map_err(Error::Connect)?;
+        stream.flush().await.map_err(Error::Connect)?;
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).await.map_err(Error::Connect)?;
+        if reply[0] != 5 {
+            return Err(Error::Proxy(format!("unexpected SOCKS version {}", reply[0])));
+        }
+        match (reply[1], &self.credentials) {
+            (0, _) => {}
+            (2, Some((username, password))) => {
+                if username.len() > 255 || password.len() > 255 {
+                    return Err(Error::Proxy("SOCKS5 credentials too long".to_owned()));
+                }
+                let mut request = vec![1, username.len() as u8];
+                request.extend_from_slice(username.as_bytes());
+                request.push(password.len() as u8);
+                request.extend_from_slice(password.as_bytes());
+                stream.write_all(&request).await.map_err(Error::Connect)?;
+                stream.flush().await.map_err(Error::Connect)?;
+                stream.read_exact(&mut reply).await.map_err(Error::Connect)?;
+                if reply[1] != 0 {
+                    return Err(Error::Proxy("SOCKS5 authentication failed".to_owned()));
+                }
+            }
+            _ => return Err(Error::Proxy("no acceptable SOCKS5 authentication method".to_owned())),
+        }
+
+        // Connect request.
+        let mut request = vec![5, 1, 0];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(addr)) => {
+                request.push(1);
+                request.extend_from_slice(&addr.octets());
+            }
+            Ok(IpAddr::V6(addr)) => {
+                request.push(4);
+                request.extend_from_slice(&addr.octets());
+            }
+            Err(_) => {
+                if host.len() > 255 {
+                    return Err(Error::Proxy("host name too long for SOCKS5".to_owned()));
+                }
+                request.push(3);
+                request.push(host.len() as u8);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await.map_err(Error::Connect)?;
+        stream.flush().await.map_err(Error::Connect)?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await.map_err(Error::Connect)?;
+        if reply[0] != 5 {
+            return Err(Error::Proxy(format!("unexpected SOCKS version {}", reply[0])));
+        }
+        if reply[1] != 0 {
+            return Err(Error::Proxy(format!("SOCKS5 connect failed: {}", socks5_reply_message(reply[1]))));
+        }
+        // Skip the bound address and port.
+        let addr_len = match reply[3] {
+            1 => 4,
+            4 => 16,
+            3 => {
+                let mut len = [0];
+                stream.read_exact(&mut len).await.map_err(Error::Connect)?;
+                len[0] as usize
+            }
+            atyp => return Err(Error::Proxy(format!("unknown SOCKS5 address type {}", atyp))),
+        };
+        let mut bound = vec![0; addr_len + 2];
+        stream.read_exact(&mut bound).await.map_err(Error::Connect)?;
+        Ok(())
+    }
+}
+
+fn socks5_reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity((input.len() + 2) / 3 * 4);
+    for chunk in input.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
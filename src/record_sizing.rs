@@ -0,0 +1,61 @@
+use crate::MAX_PLAINTEXT_LEN;
+
+/// Controls how writes to a `TlsStream` are split into TLS records.
+///
+/// With any policy other than `Unbuffered`, written data is buffered until
+/// enough is available to fill a record, and `poll_flush` sends whatever is
+/// buffered as a (possibly shorter) record. Callers must therefore flush at
+/// message boundaries, just as with a `BufWriter`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RecordSizing {
+    /// Every write is passed straight to the TLS engine, producing at least
+    /// one record per write.
+    Unbuffered,
+    /// Writes are buffered into records carrying this many bytes of
+    /// plaintext.
+    ///
+    /// Values are clamped to the range `1..=16384`.
+    Fixed(usize),
+    /// Records start out carrying `initial` bytes of plaintext, so the first
+    /// bytes of a response arrive without waiting for a full 16 KiB record,
+    /// and double in size after each full record up to the 16 KiB maximum.
+    /// Flushing the stream ends the message, so records go back to `initial`
+    /// bytes afterwards.
+    ///
+    /// A common choice for `initial` is a size fitting in a single TCP
+    /// segment, such as 1300 bytes.
+    Dynamic {
+        initial: usize,
+    },
+}
+
+impl Default for RecordSizing {
+    fn default() -> Self {
+        RecordSizing::Unbuffered
+    }
+}
+
+impl RecordSizing {
+    /// The record size to start writing with, or `None` if unbuffered.
+    pub(crate) fn initial_record_size(self) -> Option<usize> {
+        match self {
+            RecordSizing::Unbuffered => None,
+            RecordSizing::Fixed(size) | RecordSizing::Dynamic { initial: size } => {
+                Some(clamp_record_size(size))
+            }
+        }
+    }
+
+    /// The record size to use after a full record of `current` bytes was
+    /// written.
+    pub(crate) fn next_record_size(self, current: usize) -> usize {
+        match self {
+            RecordSizing::Dynamic { .. } => clamp_record_size(current.saturating_mul(2)),
+            _ => current,
+        }
+    }
+}
+
+fn clamp_record_size(size: usize) -> usize {
+    std::cmp::max(1, std::cmp::min(size, MAX_PLAINTEXT_LEN))
+}
@@ -0,0 +1,136 @@
+use crate::errors::Error;
+use crate::Certificate;
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use x509_parser::pem::parse_x509_pem;
+use x509_parser::{parse_x509_certificate, parse_x509_crl};
+
+/// A certificate revocation list, loaded with `from_der` or `from_pem`.
+///
+/// The list is parsed when it is loaded and only what is needed to check a
+/// certificate against it is kept.
+///
+/// Its signature is not verified, and it applies to every certificate whose
+/// issuer has the same distinguished name as the list's issuer. A list is
+/// trusted because the application supplied it: it must come from the CA
+/// itself or a channel as trusted as the root certificates, since anyone
+/// able to substitute it can revoke certificates, or hide revocations.
+///
+/// Only the peer's leaf certificate is checked against it, because that is
+/// the only certificate `native-tls` exposes. There is no option to check
+/// the whole chain, so a revoked intermediate goes unnoticed unless the
+/// leaves it issued are revoked as well.
+#[derive(Debug, Clone)]
+pub struct CertificateRevocationList {
+    // DER of the issuer's distinguished name.
+    issuer: Vec<u8>,
+    next_update: Option<SystemTime>,
+    // DER contents of the serial numbers of revoked certificates.
+    revoked: Vec<Vec<u8>>,
+}
+
+impl CertificateRevocationList {
+    /// Parses a DER-formatted CRL.
+    pub fn from_der(der: &[u8]) -> Result<CertificateRevocationList, Error> {
+        let (_, crl) = parse_x509_crl(der).map_err(|e| Error::InvalidCrl(e.to_string()))?;
+        Ok(CertificateRevocationList {
+            issuer: crl.issuer().as_raw().to_vec(),
+            next_update: crl.next_update().map(|t| {
+                // Dates before 1970 are as good as expired.
+                UNIX_EPOCH + Duration::from_secs(t.timestamp().max(0) as u64)
+            }),
+            revoked: crl.iter_revoked_certificates().map(|r| r.raw_serial().to_vec()).collect(),
+        })
+    }
+
+    /// Parses a PEM-formatted CRL.
+    pub fn from_pem(pem: &[u8]) -> Result<CertificateRevocationList, Error> {
+        let (_, pem) = parse_x509_pem(pem).map_err(|e| Error::InvalidCrl(e.to_string()))?;
+        if pem.label != "X509 CRL" {
+            return Err(Error::InvalidCrl(format!("unexpected PEM label {:?}", pem.label)));
+        }
+        CertificateRevocationList::from_der(&pem.contents)
+    }
+
+    fn is_stale(&self, now: SystemTime) -> bool {
+        self.next_update.map_or(false, |next_update| next_update < now)
+    }
+}
+
+/// How a certificate is treated when no up-to-date CRL covers it, or when it
+/// cannot be parsed to look it up.
+///
+/// A CRL covers a certificate if it was issued by the certificate's issuer,
+/// and is stale once its `nextUpdate` time has passed. A certificate listed
+/// in any covering CRL, stale or not, is always rejected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RevocationPolicy {
+    /// Accept the certificate, logging that it could not be checked.
+    SoftFail,
+    /// Reject the certificate with `Error::RevocationUnavailable`.
+    HardFail,
+}
+
+impl Default for RevocationPolicy {
+    fn default() -> Self {
+        RevocationPolicy::HardFail
+    }
+}
+
+/// The CRLs configured on a `TlsConnector`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RevocationConfig {
+    pub(crate) crls: Vec<Arc<CertificateRevocationList>>,
+    pub(crate) policy: RevocationPolicy,
+}
+
+impl RevocationConfig {
+    /// Checks the peer's leaf certificate against the configured CRLs.
+    ///
+    /// The rest of the chain is not available from `native-tls`, so it is
+    /// not checked.
+    ///
+    /// Nothing is checked if no CRL was configured or the peer did not
+    /// present a certificate.
+    pub(crate) fn check(&self, peer_certificate: Option<&Certificate>) -> Result<(), Error> {
+        let peer_certificate = match peer_certificate {
+            Some(cert) if !self.crls.is_empty() => cert,
+            _ => return Ok(()),
+        };
+        let der = peer_certificate.to_der().map_err(Error::Native)?;
+        let cert = match parse_x509_certificate(&der) {
+            Ok((_, cert)) => cert,
+            Err(e) => return self.unavailable(format!("cannot parse peer certificate: {}", e)),
+        };
+        let issuer = cert.issuer().as_raw();
+        let serial = cert.raw_serial();
+
+        let now = SystemTime::now();
+        let mut up_to_date = false;
+        for crl in self.crls.iter().filter(|crl| crl.issuer == issuer) {
+            if crl.revoked.iter().any(|revoked| revoked.as_slice() == serial) {
+                return Err(Error::Revoked);
+            }
+            up_to_date |= !crl.is_stale(now);
+        }
+        if up_to_date {
+            return Ok(());
+        }
+
+        self.unavailable(format!("no up-to-date CRL for issuer {}", cert.issuer()))
+    }
+
+    /// Applies the policy to a certificate that could not be checked.
+    fn unavailable(&self, reason: String) -> Result<(), Error> {
+        match self.policy {
+            RevocationPolicy::SoftFail => {
+                debug!("Revocation was not checked: {}", reason);
+                Ok(())
+            }
+            RevocationPolicy::HardFail => Err(Error::RevocationUnavailable(reason)),
+        }
+    }
+}
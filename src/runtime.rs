@@ -0,0 +1,107 @@
+//! Bridges between the `futures-io` traits used by async-std and smol and the
+//! `futures-preview` traits this crate is built on.
+use crate::TlsStream;
+
+use std::io;
+use std::pin::Pin;
+use std::task::Context;
+
+use futures::io::{AsyncRead, AsyncWrite, IoSlice, IoSliceMut};
+use futures::Poll;
+
+/// A stream implementing the `futures-io` traits, such as the sockets of
+/// async-std and smol, wrapped to implement the `AsyncRead`/`AsyncWrite`
+/// traits of this crate.
+///
+/// `TlsStream` implements the `futures-io` traits in turn, so a
+/// `TlsStream<FuturesIo<S>>` works with the I/O utilities of either runtime.
+#[derive(Debug)]
+pub struct FuturesIo<S> {
+    inner: S,
+}
+
+impl<S> FuturesIo<S> {
+    /// Wraps `inner`.
+    pub fn new(inner: S) -> Self {
+        FuturesIo { inner }
+    }
+
+    /// Get access to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get mutable access to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Returns the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: futures_io::AsyncRead + Unpin> AsyncRead for FuturesIo<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
+                 -> Poll<Result<usize, io::Error>> {
+        futures_io::AsyncRead::poll_read(Pin::new(&mut self.inner), cx, buf)
+    }
+
+    fn poll_read_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &mut [IoSliceMut<'_>])
+                          -> Poll<Result<usize, io::Error>> {
+        futures_io::AsyncRead::poll_read_vectored(Pin::new(&mut self.inner), cx, bufs)
+    }
+}
+
+impl<S: futures_io::AsyncWrite + Unpin> AsyncWrite for FuturesIo<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+                  -> Poll<Result<usize, io::Error>> {
+        futures_io::AsyncWrite::poll_write(Pin::new(&mut self.inner), cx, buf)
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>])
+                           -> Poll<Result<usize, io::Error>> {
+        futures_io::AsyncWrite::poll_write_vectored(Pin::new(&mut self.inner), cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        futures_io::AsyncWrite::poll_flush(Pin::new(&mut self.inner), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        futures_io::AsyncWrite::poll_close(Pin::new(&mut self.inner), cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> futures_io::AsyncRead for TlsStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
+                 -> Poll<Result<usize, io::Error>> {
+        AsyncRead::poll_read(self, cx, buf)
+    }
+
+    fn poll_read_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &mut [IoSliceMut<'_>])
+                          -> Poll<Result<usize, io::Error>> {
+        AsyncRead::poll_read_vectored(self, cx, bufs)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> futures_io::AsyncWrite for TlsStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+                  -> Poll<Result<usize, io::Error>> {
+        AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>])
+                           -> Poll<Result<usize, io::Error>> {
+        AsyncWrite::poll_write_vectored(self, cx, bufs)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        AsyncWrite::poll_close(self, cx)
+    }
+}
@@ -0,0 +1,68 @@
+//! Helpers to connect and accept over the TCP and Unix sockets of smol.
+//!
+//! The sockets are wrapped in a `FuturesIo`, and the resulting `TlsStream`
+//! implements the `futures-io` traits, so it works with the I/O utilities
+//! of smol. Streams are woken by smol's reactor like any other socket.
+//!
+//! This module is only available with the `smol` feature.
+use crate::runtime::FuturesIo;
+use crate::{Error, PendingTlsStream, TlsAcceptor, TlsConnector, TlsStream};
+
+#[cfg(unix)]
+use std::path::Path;
+
+use smol_crate::net::TcpStream;
+#[cfg(unix)]
+use smol_crate::net::unix::UnixStream;
+
+/// A TLS stream over a smol `TcpStream`.
+pub type TlsTcpStream = TlsStream<FuturesIo<TcpStream>>;
+
+/// A TLS stream over a smol `UnixStream`.
+#[cfg(unix)]
+pub type TlsUnixStream = TlsStream<FuturesIo<UnixStream>>;
+
+/// Connects to `host` on `port` over TCP, then performs the client half of
+/// a handshake with `host`.
+///
+/// The host is resolved and connected to by smol, without the address racing
+/// and connect timeout of `TlsConnector::connect_to`. Resolution and
+/// connection failures resolve to `Error::Connect`.
+pub async fn connect(connector: &TlsConnector, host: &str, port: u16) -> Result<TlsTcpStream, Error> {
+    let stream = TcpStream::connect((host, port)).await.map_err(Error::Connect)?;
+    let peer_addr = stream.peer_addr();
+    let pending = connector.connect(host, FuturesIo::new(stream));
+    if let Ok(peer_addr) = peer_addr {
+        pending.record_peer_addr(peer_addr);
+    }
+    pending.await
+}
+
+/// Connects to the Unix socket at `path`, then performs the client half of a
+/// handshake with `domain`.
+///
+/// Connection failures resolve to `Error::Connect`.
+#[cfg(unix)]
+pub async fn connect_unix<P: AsRef<Path>>(connector: &TlsConnector, domain: &str, path: P)
+                                          -> Result<TlsUnixStream, Error> {
+    let stream = UnixStream::connect(path.as_ref()).await.map_err(Error::Connect)?;
+    connector.connect(domain, FuturesIo::new(stream)).await
+}
+
+/// Performs the server half of a handshake over a TCP connection accepted
+/// from a smol `TcpListener`.
+pub fn accept(acceptor: &TlsAcceptor, stream: TcpStream) -> PendingTlsStream<FuturesIo<TcpStream>> {
+    let peer_addr = stream.peer_addr();
+    let pending = acceptor.accept(FuturesIo::new(stream));
+    if let Ok(peer_addr) = peer_addr {
+        pending.record_peer_addr(peer_addr);
+    }
+    pending
+}
+
+/// Performs the server half of a handshake over a connection accepted from a
+/// smol `UnixListener`.
+#[cfg(unix)]
+pub fn accept_unix(acceptor: &TlsAcceptor, stream: UnixStream) -> PendingTlsStream<FuturesIo<UnixStream>> {
+    acceptor.accept(FuturesIo::new(stream))
+}
@@ -0,0 +1,201 @@
+//! Helpers for testing code built on this crate without real sockets.
+//!
+//! `duplex` creates a pair of connected in-memory streams, and
+//! `connected_pair` runs a TLS handshake over such a pair, resolving to a
+//! client and a server `TlsStream` talking to each other. `TestCa` mints
+//! certificates for such tests without any external tooling, and
+//! `localhost_contexts` sets up an acceptor and a connector using them.
+//! `thread_timer` is a `Timer` needing no runtime support.
+//!
+//! Like any `TlsStream`, the streams and futures returned here can be polled
+//! by any executor, e.g. `futures::executor::block_on`.
+//!
+//! This module is only available with the `testing` feature.
+mod ca;
+
+pub use self::ca::{CertificateBuilder, KeyType, TestCa, TestIdentity};
+
+use crate::acceptor::TlsAcceptorBuilder;
+use crate::connector::TlsConnectorBuilder;
+use crate::{Delay, Error, PendingTlsStream, TlsAcceptor, TlsConnector};
+
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Waker};
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::future::{try_join, TryJoin};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::{FutureExt, Poll};
+
+/// The buffer size used for each direction by `connected_pair`.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    max_buf_size: usize,
+    closed: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl Pipe {
+    fn new(max_buf_size: usize) -> Arc<Mutex<Pipe>> {
+        Arc::new(Mutex::new(Pipe {
+            max_buf_size,
+            ..Pipe::default()
+        }))
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// One end of an in-memory, bidirectional byte stream created by `duplex`.
+///
+/// Bytes written to one end can be read from the other. Closing an end makes
+/// reads on the other end return EOF once the buffered bytes are consumed.
+/// Dropping an end additionally makes writes on the other end fail with
+/// `io::ErrorKind::BrokenPipe`.
+#[derive(Debug)]
+pub struct DuplexStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+/// Creates a pair of connected in-memory streams.
+///
+/// Each direction buffers at most `max_buf_size` bytes; writes beyond that
+/// return `Poll::Pending` until the other end reads.
+pub fn duplex(max_buf_size: usize) -> (DuplexStream, DuplexStream) {
+    assert!(max_buf_size > 0, "max_buf_size must be greater than zero");
+    let one = Pipe::new(max_buf_size);
+    let two = Pipe::new(max_buf_size);
+    let a = DuplexStream {
+        read: one.clone(),
+        write: two.clone(),
+    };
+    let b = DuplexStream {
+        read: two,
+        write: one,
+    };
+    (a, b)
+}
+
+/// Performs a handshake between `connector` and `acceptor` over a new
+/// `duplex` pair, resolving to the client and server streams in that order.
+///
+/// `domain` is the name the client verifies the server certificate against.
+/// The returned future resolves to
+/// `Result<(TlsStream<DuplexStream>, TlsStream<DuplexStream>), Error>`.
+pub fn connected_pair(connector: &TlsConnector, domain: &str, acceptor: &TlsAcceptor) -> ConnectedPair {
+    let (client, server) = duplex(DEFAULT_BUFFER_SIZE);
+    try_join(connector.connect(domain, client), acceptor.accept(server))
+}
+
+/// The future returned by `connected_pair`.
+pub type ConnectedPair = TryJoin<PendingTlsStream<DuplexStream>, PendingTlsStream<DuplexStream>>;
+
+/// Returns builders for a `TlsAcceptor` presenting a certificate for
+/// `localhost` issued by `issuer`, and for a `TlsConnector` trusting `ca`.
+///
+/// `issuer` is usually `ca` itself, or an intermediate created from it.
+pub fn localhost_builders(ca: &TestCa, issuer: &TestCa)
+                          -> Result<(TlsAcceptorBuilder, TlsConnectorBuilder), Error> {
+    let leaf = issuer.leaf(CertificateBuilder::new("localhost").dns_name("localhost"))?;
+    let acceptor = TlsAcceptor::builder(leaf.identity()?);
+    let mut connector = TlsConnector::builder();
+    connector.add_root_certificate(ca.certificate()?);
+    Ok((acceptor, connector))
+}
+
+/// Like `localhost_builders`, but returns the built `TlsAcceptor` and
+/// `TlsConnector` with their default settings.
+pub fn localhost_contexts(ca: &TestCa, issuer: &TestCa) -> Result<(TlsAcceptor, TlsConnector), Error> {
+    let (acceptor, connector) = localhost_builders(ca, issuer)?;
+    Ok((acceptor.build()?, connector.build()?))
+}
+
+/// A `Timer` running each delay on a thread of its own.
+///
+/// It works from any executor, which makes it convenient for tests, but is
+/// too expensive for anything else.
+pub fn thread_timer(duration: Duration) -> Delay {
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        thread::sleep(duration);
+        drop(tx.send(()));
+    });
+    Box::pin(rx.map(|_| ()))
+}
+
+impl AsyncRead for DuplexStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
+                 -> Poll<Result<usize, io::Error>> {
+        let mut pipe = self.read.lock().unwrap();
+        if pipe.buf.is_empty() {
+            if pipe.closed || buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            pipe.read_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let sz = std::cmp::min(buf.len(), pipe.buf.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..sz)) {
+            *dst = src;
+        }
+        if let Some(waker) = pipe.write_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(sz))
+    }
+}
+
+impl AsyncWrite for DuplexStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+                  -> Poll<Result<usize, io::Error>> {
+        let mut pipe = self.write.lock().unwrap();
+        if pipe.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let available = pipe.max_buf_size - pipe.buf.len();
+        if available == 0 {
+            pipe.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let sz = std::cmp::min(buf.len(), available);
+        pipe.buf.extend(&buf[..sz]);
+        if let Some(waker) = pipe.read_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(sz))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        self.write.lock().unwrap().close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        self.write.lock().unwrap().close();
+        self.read.lock().unwrap().close();
+    }
+}
@@ -0,0 +1,283 @@
+use crate::errors::Error;
+use crate::{Certificate, Identity};
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use rcgen::{
+    BasicConstraints, CertificateRevocationListParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyIdMethod, KeyUsagePurpose, RevokedCertParams, SanType,
+    SerialNumber,
+};
+
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(1);
+
+/// The type of key generated for a certificate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyType {
+    /// ECDSA with the NIST P-256 curve, signed with SHA-256.
+    EcdsaP256,
+    /// ECDSA with the NIST P-384 curve, signed with SHA-384.
+    EcdsaP384,
+    /// Ed25519.
+    ///
+    /// Not every backend supports Ed25519 certificates.
+    Ed25519,
+}
+
+impl KeyType {
+    fn algorithm(self) -> &'static rcgen::SignatureAlgorithm {
+        match self {
+            KeyType::EcdsaP256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+            KeyType::EcdsaP384 => &rcgen::PKCS_ECDSA_P384_SHA384,
+            KeyType::Ed25519 => &rcgen::PKCS_ED25519,
+        }
+    }
+}
+
+/// Describes a certificate to be minted by a `TestCa`.
+#[derive(Debug, Clone)]
+pub struct CertificateBuilder {
+    common_name: String,
+    subject_alt_names: Vec<SanType>,
+    not_before: SystemTime,
+    not_after: SystemTime,
+    key_type: KeyType,
+}
+
+impl CertificateBuilder {
+    /// Starts describing a certificate with the given common name.
+    ///
+    /// The certificate is valid from one hour ago until one day from now and
+    /// uses a `KeyType::EcdsaP256` key unless configured otherwise.
+    pub fn new(common_name: &str) -> CertificateBuilder {
+        let now = SystemTime::now();
+        CertificateBuilder {
+            common_name: common_name.to_owned(),
+            subject_alt_names: Vec::new(),
+            not_before: now - Duration::from_secs(60 * 60),
+            not_after: now + Duration::from_secs(24 * 60 * 60),
+            key_type: KeyType::EcdsaP256,
+        }
+    }
+
+    /// Adds a DNS name subject alternative name.
+    pub fn dns_name(&mut self, name: &str) -> &mut CertificateBuilder {
+        self.subject_alt_names.push(SanType::DnsName(name.to_owned()));
+        self
+    }
+
+    /// Adds an IP address subject alternative name.
+    pub fn ip_address(&mut self, addr: IpAddr) -> &mut CertificateBuilder {
+        self.subject_alt_names.push(SanType::IpAddress(addr));
+        self
+    }
+
+    /// Adds a URI subject alternative name, e.g. a SPIFFE ID.
+    pub fn uri(&mut self, uri: &str) -> &mut CertificateBuilder {
+        self.subject_alt_names.push(SanType::URI(uri.to_owned()));
+        self
+    }
+
+    /// Sets the validity window of the certificate.
+    ///
+    /// A window in the past or the future can be used to test expired or not
+    /// yet valid certificates.
+    pub fn validity(&mut self, not_before: SystemTime, not_after: SystemTime) -> &mut CertificateBuilder {
+        self.not_before = not_before;
+        self.not_after = not_after;
+        self
+    }
+
+    /// Sets the type of key generated for the certificate.
+    pub fn key_type(&mut self, key_type: KeyType) -> &mut CertificateBuilder {
+        self.key_type = key_type;
+        self
+    }
+
+    fn params(&self, is_ca: IsCa, serial: u64) -> rcgen::CertificateParams {
+        let mut params = rcgen::CertificateParams::default();
+        let mut distinguished_name = DistinguishedName::new();
+        distinguished_name.push(DnType::CommonName, self.common_name.clone());
+        params.distinguished_name = distinguished_name;
+        params.subject_alt_names = self.subject_alt_names.clone();
+        params.not_before = self.not_before.into();
+        params.not_after = self.not_after.into();
+        params.alg = self.key_type.algorithm();
+        params.serial_number = Some(SerialNumber::from(serial));
+        match is_ca {
+            IsCa::Ca(_) => {
+                params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+            }
+            _ => {
+                params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+                params.extended_key_usages = vec![
+                    ExtendedKeyUsagePurpose::ServerAuth,
+                    ExtendedKeyUsagePurpose::ClientAuth,
+                ];
+            }
+        }
+        params.is_ca = is_ca;
+        params
+    }
+}
+
+/// A certificate authority for tests, able to mint intermediates and leaf
+/// certificates without any external tooling.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use tls_async::testing::{CertificateBuilder, TestCa};
+/// use tls_async::{TlsAcceptor, TlsConnector};
+///
+/// let root = TestCa::new().unwrap();
+/// let leaf = root.leaf(CertificateBuilder::new("localhost").dns_name("localhost")).unwrap();
+///
+/// let acceptor = TlsAcceptor::new(leaf.identity().unwrap()).unwrap();
+/// let connector = TlsConnector::builder()
+///     .add_root_certificate(root.certificate().unwrap())
+///     .build()
+///     .unwrap();
+/// ```
+pub struct TestCa {
+    cert: rcgen::Certificate,
+    pem: String,
+    // PEM of this CA, if it is an intermediate, and of the intermediates
+    // between it and the root, nearest first.
+    chain: Vec<String>,
+}
+
+impl TestCa {
+    /// Creates a self-signed root CA with default settings.
+    pub fn new() -> Result<TestCa, Error> {
+        TestCa::root(&CertificateBuilder::new("tls-async test CA"))
+    }
+
+    /// Creates a self-signed root CA described by `builder`.
+    pub fn root(builder: &CertificateBuilder) -> Result<TestCa, Error> {
+        let params = builder.params(IsCa::Ca(BasicConstraints::Unconstrained), next_serial());
+        let cert = rcgen::Certificate::from_params(params).map_err(test_certificate_error)?;
+        let pem = cert.serialize_pem().map_err(test_certificate_error)?;
+        Ok(TestCa {
+            cert,
+            pem,
+            chain: Vec::new(),
+        })
+    }
+
+    /// Creates an intermediate CA described by `builder` and signed by this
+    /// CA.
+    pub fn intermediate(&self, builder: &CertificateBuilder) -> Result<TestCa, Error> {
+        let params = builder.params(IsCa::Ca(BasicConstraints::Unconstrained), next_serial());
+        let cert = rcgen::Certificate::from_params(params).map_err(test_certificate_error)?;
+        let pem = cert.serialize_pem_with_signer(&self.cert).map_err(test_certificate_error)?;
+        let mut chain = vec![pem.clone()];
+        chain.extend(self.chain.iter().cloned());
+        Ok(TestCa {
+            cert,
+            pem,
+            chain,
+        })
+    }
+
+    /// Creates a leaf certificate described by `builder` and signed by this
+    /// CA.
+    ///
+    /// The certificate can be used both as a server and a client identity.
+    pub fn leaf(&self, builder: &CertificateBuilder) -> Result<TestIdentity, Error> {
+        let serial = next_serial();
+        let params = builder.params(IsCa::NoCa, serial);
+        let cert = rcgen::Certificate::from_params(params).map_err(test_certificate_error)?;
+        let pem = cert.serialize_pem_with_signer(&self.cert).map_err(test_certificate_error)?;
+        Ok(TestIdentity {
+            pem,
+            key_pem: cert.serialize_private_key_pem(),
+            chain: self.chain.clone(),
+            serial,
+        })
+    }
+
+    /// Creates a CRL signed by this CA listing `revoked` as revoked, in PEM
+    /// format.
+    ///
+    /// The CRL was last updated one hour ago and is next updated at
+    /// `next_update`; a time in the past yields a stale CRL.
+    pub fn crl_pem(&self, revoked: &[&TestIdentity], next_update: SystemTime) -> Result<String, Error> {
+        let now = SystemTime::now();
+        let params = CertificateRevocationListParams {
+            this_update: (now - Duration::from_secs(60 * 60)).into(),
+            next_update: next_update.into(),
+            crl_number: SerialNumber::from(next_serial()),
+            issuing_distribution_point: None,
+            revoked_certs: revoked.iter().map(|identity| RevokedCertParams {
+                serial_number: SerialNumber::from(identity.serial),
+                revocation_time: now.into(),
+                reason_code: None,
+                invalidity_date: None,
+            }).collect(),
+            alg: self.cert.get_params().alg,
+            key_identifier_method: KeyIdMethod::Sha256,
+        };
+        let crl = rcgen::CertificateRevocationList::from_params(params).map_err(test_certificate_error)?;
+        crl.serialize_pem_with_signer(&self.cert).map_err(test_certificate_error)
+    }
+
+    /// Returns the certificate of this CA, e.g. for
+    /// `TlsConnectorBuilder::add_root_certificate`.
+    pub fn certificate(&self) -> Result<Certificate, Error> {
+        Certificate::from_pem(self.pem.as_bytes()).map_err(Error::Native)
+    }
+
+    /// Returns the certificate of this CA in PEM format.
+    pub fn certificate_pem(&self) -> &str {
+        &self.pem
+    }
+}
+
+/// A leaf certificate and its private key, minted by a `TestCa`.
+#[derive(Debug, Clone)]
+pub struct TestIdentity {
+    pem: String,
+    key_pem: String,
+    // PEM of the intermediates between the leaf and the root, nearest first.
+    chain: Vec<String>,
+    serial: u64,
+}
+
+impl TestIdentity {
+    /// Returns an `Identity` containing the leaf certificate, the
+    /// intermediates up to (but excluding) the root and the private key,
+    /// ready for `TlsAcceptor::new` or `TlsConnectorBuilder::identity`.
+    pub fn identity(&self) -> Result<Identity, Error> {
+        let mut pem = self.pem.clone();
+        for intermediate in &self.chain {
+            pem.push_str(intermediate);
+        }
+        Identity::from_pkcs8(pem.as_bytes(), self.key_pem.as_bytes()).map_err(Error::Native)
+    }
+
+    /// Returns the leaf certificate.
+    pub fn certificate(&self) -> Result<Certificate, Error> {
+        Certificate::from_pem(self.pem.as_bytes()).map_err(Error::Native)
+    }
+
+    /// Returns the leaf certificate in PEM format.
+    pub fn certificate_pem(&self) -> &str {
+        &self.pem
+    }
+
+    /// Returns the PKCS #8 private key in PEM format.
+    pub fn private_key_pem(&self) -> &str {
+        &self.key_pem
+    }
+}
+
+fn next_serial() -> u64 {
+    NEXT_SERIAL.fetch_add(1, Ordering::Relaxed)
+}
+
+fn test_certificate_error(e: rcgen::RcgenError) -> Error {
+    Error::TestCertificate(e.to_string())
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::time::Duration;
+
+use futures::{Future, Poll};
+
+/// A future completing once a `Timer`'s delay has elapsed.
+pub type Delay = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Creates delays for the timeouts of a `TlsStream`.
+///
+/// This lets timeouts work with any executor: implement it on top of the
+/// timer of the runtime in use, e.g. `tokio-timer`'s `Delay`.
+///
+/// Closures of the form `Fn(Duration) -> Delay` implement this trait.
+pub trait Timer: Send + Sync {
+    /// Returns a future completing after `duration`.
+    fn delay(&self, duration: Duration) -> Delay;
+}
+
+impl<F> Timer for F
+    where F: Fn(Duration) -> Delay + Send + Sync,
+{
+    fn delay(&self, duration: Duration) -> Delay {
+        (self)(duration)
+    }
+}
+
+/// The timeouts configured on a connector or acceptor.
+#[derive(Clone, Default)]
+pub(crate) struct TimeoutConfig {
+    pub(crate) timer: Option<Arc<dyn Timer>>,
+    pub(crate) idle: Option<Duration>,
+    pub(crate) read: Option<Duration>,
+    pub(crate) write: Option<Duration>,
+    pub(crate) connect: Option<Duration>,
+    pub(crate) handshake: Option<Duration>,
+}
+
+impl TimeoutConfig {
+    /// Starts a delay for `timeout`, if both it and a timer are set.
+    pub(crate) fn delay(&self, timeout: Option<Duration>) -> Option<Delay> {
+        match (&self.timer, timeout) {
+            (Some(timer), Some(timeout)) => Some(timer.delay(timeout)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Read,
+    Write,
+}
+
+/// The timeouts of a `TlsStream` and the delays currently running for them.
+#[derive(Default)]
+pub(crate) struct Timeouts {
+    config: TimeoutConfig,
+    idle_delay: Option<Delay>,
+    read_delay: Option<Delay>,
+    write_delay: Option<Delay>,
+}
+
+impl Timeouts {
+    pub(crate) fn new(config: TimeoutConfig) -> Self {
+        Timeouts {
+            config,
+            ..Timeouts::default()
+        }
+    }
+
+    pub(crate) fn idle(&self) -> Option<Duration> {
+        self.config.idle
+    }
+
+    pub(crate) fn read(&self) -> Option<Duration> {
+        self.config.read
+    }
+
+    pub(crate) fn write(&self) -> Option<Duration> {
+        self.config.write
+    }
+
+    pub(crate) fn set_idle(&mut self, timeout: Option<Duration>) {
+        self.config.idle = timeout;
+        self.idle_delay = None;
+    }
+
+    pub(crate) fn set_read(&mut self, timeout: Option<Duration>) {
+        self.config.read = timeout;
+        self.read_delay = None;
+    }
+
+    pub(crate) fn set_write(&mut self, timeout: Option<Duration>) {
+        self.config.write = timeout;
+        self.write_delay = None;
+    }
+
+    pub(crate) fn set_timer(&mut self, timer: Arc<dyn Timer>) {
+        self.config.timer = Some(timer);
+        self.reset();
+    }
+
+    /// Stops the delays left running by pending operations, so the next
+    /// read or write starts its timeouts afresh.
+    pub(crate) fn reset(&mut self) {
+        self.idle_delay = None;
+        self.read_delay = None;
+        self.write_delay = None;
+    }
+
+    /// Records that an operation in `direction` completed, restarting its
+    /// timeout and the idle timeout.
+    pub(crate) fn progress(&mut self, direction: Direction) {
+        self.idle_delay = None;
+        match direction {
+            Direction::Read => self.read_delay = None,
+            Direction::Write => self.write_delay = None,
+        }
+    }
+
+    /// Called while an operation in `direction` is pending, starting its
+    /// timeouts if needed and failing with `io::ErrorKind::TimedOut` once
+    /// one of them has elapsed.
+    pub(crate) fn poll_elapsed(&mut self, direction: Direction, cx: &mut Context<'_>) -> Result<(), io::Error> {
+        let timer = match self.config.timer {
+            Some(ref timer) => timer,
+            None => return Ok(()),
+        };
+        let (timeout, delay, message) = match direction {
+            Direction::Read => (self.config.read, &mut self.read_delay, "TLS read timed out"),
+            Direction::Write => (self.config.write, &mut self.write_delay, "TLS write timed out"),
+        };
+        if poll_delay(&**timer, timeout, delay, cx) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, message));
+        }
+        if poll_delay(&**timer, self.config.idle, &mut self.idle_delay, cx) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "TLS connection idle timeout"));
+        }
+        Ok(())
+    }
+}
+
+/// Polls the delay for `timeout`, starting it if needed. Returns `true`, and
+/// clears the delay, once it has elapsed.
+fn poll_delay(timer: &dyn Timer, timeout: Option<Duration>, delay: &mut Option<Delay>, cx: &mut Context<'_>) -> bool {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return false,
+    };
+    let elapsed = delay
+        .get_or_insert_with(|| timer.delay(timeout))
+        .as_mut()
+        .poll(cx)
+        .is_ready();
+    if elapsed {
+        *delay = None;
+    }
+    elapsed
+}
+
+impl fmt::Debug for Timeouts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timeouts")
+            .field("idle", &self.config.idle)
+            .field("read", &self.config.read)
+            .field("write", &self.config.write)
+            .finish()
+    }
+}
+
+/// Applies the timeouts of `direction` to the result of an I/O operation.
+pub(crate) fn apply<T>(timeouts: &mut Timeouts, direction: Direction, cx: &mut Context<'_>, res: Poll<Result<T, io::Error>>)
+                       -> Poll<Result<T, io::Error>> {
+    match res {
+        Poll::Pending => match timeouts.poll_elapsed(direction, cx) {
+            Ok(()) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)),
+        },
+        res => {
+            timeouts.progress(direction);
+            res
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::errors::Error;
+use crate::metrics::Role;
+
+#[cfg(feature = "romio")]
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The `tracing` span covering one connection, from the start of its
+/// handshake until it is closed.
+///
+/// Without the `tracing` feature this is a no-op.
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionSpan(tracing::Span);
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionSpan;
+
+#[cfg(feature = "tracing")]
+impl ConnectionSpan {
+    pub(crate) fn new(role: Role, sni: Option<&str>) -> Self {
+        let span = tracing::debug_span!(
+            "tls",
+            role = ?role,
+            sni = tracing::field::Empty,
+            peer.addr = tracing::field::Empty,
+        );
+        if let Some(sni) = sni {
+            span.record("sni", &sni);
+        }
+        ConnectionSpan(span)
+    }
+
+    #[cfg(feature = "romio")]
+    pub(crate) fn record_peer_addr(&self, addr: SocketAddr) {
+        self.0.record("peer.addr", &tracing::field::display(addr));
+    }
+
+    pub(crate) fn span(&self) -> &tracing::Span {
+        &self.0
+    }
+
+    pub(crate) fn in_scope<F: FnOnce() -> T, T>(&self, f: F) -> T {
+        self.0.in_scope(f)
+    }
+
+    pub(crate) fn handshake_step(&self, pending: bool) {
+        self.0.in_scope(|| tracing::trace!(pending, "handshake step"));
+    }
+
+    pub(crate) fn handshake_succeeded(&self, duration: Duration) {
+        self.0.in_scope(|| tracing::debug!(?duration, "handshake succeeded"));
+    }
+
+    pub(crate) fn handshake_failed(&self, duration: Duration, error: &Error) {
+        self.0.in_scope(|| tracing::debug!(?duration, %error, "handshake failed"));
+    }
+
+    pub(crate) fn event(&self, message: &'static str) {
+        self.0.in_scope(|| tracing::debug!("{}", message));
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl ConnectionSpan {
+    pub(crate) fn new(_role: Role, _sni: Option<&str>) -> Self {
+        ConnectionSpan
+    }
+
+    #[cfg(feature = "romio")]
+    pub(crate) fn record_peer_addr(&self, _addr: SocketAddr) {}
+
+    pub(crate) fn in_scope<F: FnOnce() -> T, T>(&self, f: F) -> T {
+        f()
+    }
+
+    pub(crate) fn handshake_step(&self, _pending: bool) {}
+
+    pub(crate) fn handshake_succeeded(&self, _duration: Duration) {}
+
+    pub(crate) fn handshake_failed(&self, _duration: Duration, _error: &Error) {}
+
+    pub(crate) fn event(&self, _message: &'static str) {}
+}
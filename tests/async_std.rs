@@ -0,0 +1,80 @@
+#![feature(async_await)]
+extern crate async_std_crate as async_std;
+
+use std::time::Duration;
+
+use async_std::net::TcpListener;
+use async_std::task;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tls_async::testing::{localhost_contexts, TestCa};
+
+macro_rules! t {
+    ($e:expr) => (match $e {
+        Ok(e) => e,
+        Err(e) => panic!("{} failed with {:?}", stringify!($e), e),
+    })
+}
+
+/// Waits until the client is blocked reading, so that only the reactor can
+/// wake it once the response arrives, then responds and closes the stream.
+async fn respond_later<S: AsyncWrite + Unpin>(mut stream: S) {
+    task::sleep(Duration::from_millis(100)).await;
+    t!(stream.write_all(b"hello").await);
+    t!(stream.close().await);
+}
+
+async fn read_all<S: AsyncRead + Unpin>(mut stream: S) -> Vec<u8> {
+    let mut buf = vec![];
+    t!(stream.read_to_end(&mut buf).await);
+    buf
+}
+
+#[test]
+fn tcp_read_is_woken_by_reactor() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let data = task::block_on(async move {
+        let listener = t!(TcpListener::bind("127.0.0.1:0").await);
+        let port = t!(listener.local_addr()).port();
+        let server = task::spawn(async move {
+            let (socket, _) = t!(listener.accept().await);
+            let stream = t!(tls_async::async_std::accept(&acceptor, socket).await);
+            respond_later(stream).await;
+        });
+        let stream = t!(tls_async::async_std::connect(&connector, "localhost", port).await);
+        let data = read_all(stream).await;
+        server.await;
+        data
+    });
+    assert_eq!(data, b"hello");
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_read_is_woken_by_reactor() {
+    use async_std::os::unix::net::UnixListener;
+
+    drop(env_logger::try_init());
+
+    let path = std::env::temp_dir().join(format!("tls-async-async-std-{}.sock", std::process::id()));
+    drop(std::fs::remove_file(&path));
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let socket_path = path.clone();
+    let data = task::block_on(async move {
+        let listener = t!(UnixListener::bind(&socket_path).await);
+        let server = task::spawn(async move {
+            let (socket, _) = t!(listener.accept().await);
+            let stream = t!(tls_async::async_std::accept_unix(&acceptor, socket).await);
+            respond_later(stream).await;
+        });
+        let stream = t!(tls_async::async_std::connect_unix(&connector, "localhost", &socket_path).await);
+        let data = read_all(stream).await;
+        server.await;
+        data
+    });
+    drop(std::fs::remove_file(&path));
+    assert_eq!(data, b"hello");
+}
@@ -0,0 +1,144 @@
+#![feature(async_await)]
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future;
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::{FutureExt, StreamExt, TryFutureExt};
+use romio::TcpListener;
+use tls_async::testing::{localhost_builders, thread_timer, TestCa};
+use tls_async::{Delay, Error, TlsAcceptor, TlsConnector};
+
+macro_rules! t {
+    ($e:expr) => (match $e {
+        Ok(e) => e,
+        Err(e) => panic!("{} failed with {:?}", stringify!($e), e),
+    })
+}
+
+fn contexts() -> (TlsAcceptor, TlsConnector) {
+    let ca = t!(TestCa::new());
+    let (acceptor, mut connector) = t!(localhost_builders(&ca, &ca));
+    connector.timer(Arc::new(thread_timer));
+    connector.connect_timeout(Some(Duration::from_secs(10)));
+    (t!(acceptor.build()), t!(connector.build()))
+}
+
+#[test]
+fn connect_to_localhost() {
+    drop(env_logger::try_init());
+
+    // `localhost` may also resolve to ::1, where nothing listens. Falling
+    // back between explicit addresses is covered by the tests in dial.rs.
+    let mut srv = t!(TcpListener::bind(&t!("127.0.0.1:0".parse())));
+    let addr = t!(srv.local_addr());
+    let (acceptor, connector) = contexts();
+
+    let fut_server = async move {
+        let mut incoming = srv.incoming();
+        let socket = t!(incoming.next().await.unwrap());
+        let mut stream = t!(acceptor.accept(socket).await);
+        t!(stream.write_all(b"hello").await);
+        t!(stream.close().await);
+    };
+
+    let fut_client = async move {
+        let mut stream = t!(connector.connect_to(&format!("localhost:{}", addr.port())).await);
+        let mut buf = vec![];
+        t!(stream.read_to_end(&mut buf).await);
+        buf
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    rt.spawn(fut_server.boxed().unit_error().compat());
+    let data = t!(rt.block_on(fut_client.boxed().unit_error().compat()));
+    assert_eq!(data, b"hello");
+}
+
+#[test]
+fn connection_refused() {
+    drop(env_logger::try_init());
+
+    // Bind and drop a listener to find a port nothing listens on.
+    let port = {
+        let srv = t!(TcpListener::bind(&t!("127.0.0.1:0".parse())));
+        t!(srv.local_addr()).port()
+    };
+    let (_, connector) = contexts();
+    let fut = async move {
+        match connector.connect_to(&format!("127.0.0.1:{}", port)).await {
+            Err(Error::Connect(_)) => {}
+            res => panic!("expected Error::Connect, got {:?}", res.map(|_| ())),
+        }
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    t!(rt.block_on(fut.boxed().unit_error().compat()));
+}
+
+#[test]
+fn invalid_address() {
+    let (_, connector) = contexts();
+    let fut = async move {
+        match connector.connect_to("localhost").await {
+            Err(Error::InvalidAddress(_)) => {}
+            res => panic!("expected Error::InvalidAddress, got {:?}", res.map(|_| ())),
+        }
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    t!(rt.block_on(fut.boxed().unit_error().compat()));
+}
+
+/// A timer whose delays have always already elapsed.
+fn expired_timer(_: Duration) -> Delay {
+    Box::pin(future::ready(()))
+}
+
+#[test]
+fn connect_timeout() {
+    drop(env_logger::try_init());
+
+    let srv = t!(TcpListener::bind(&t!("127.0.0.1:0".parse())));
+    let addr = t!(srv.local_addr());
+    let connector = t!(TlsConnector::builder()
+        .timer(Arc::new(expired_timer))
+        .connect_timeout(Some(Duration::from_secs(10)))
+        .build());
+    let fut = async move {
+        match connector.connect_to(&addr.to_string()).await {
+            Err(Error::Connect(ref e)) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            res => panic!("expected a timeout, got {:?}", res.map(|_| ())),
+        }
+        drop(srv);
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    t!(rt.block_on(fut.boxed().unit_error().compat()));
+}
+
+#[test]
+fn connect_timeout_without_timer() {
+    drop(env_logger::try_init());
+
+    let mut srv = t!(TcpListener::bind(&t!("127.0.0.1:0".parse())));
+    let addr = t!(srv.local_addr());
+    let ca = t!(TestCa::new());
+    let (acceptor, mut connector) = t!(localhost_builders(&ca, &ca));
+    let acceptor = t!(acceptor.build());
+    // Without a timer, even a zero timeout is ignored.
+    let connector = t!(connector.connect_timeout(Some(Duration::from_secs(0))).build());
+
+    let fut_server = async move {
+        let mut incoming = srv.incoming();
+        let socket = t!(incoming.next().await.unwrap());
+        t!(acceptor.accept(socket).await);
+    };
+    let fut_client = async move {
+        t!(connector.connect_to(&format!("localhost:{}", addr.port())).await);
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    rt.spawn(fut_server.boxed().unit_error().compat());
+    t!(rt.block_on(fut_client.boxed().unit_error().compat()));
+}
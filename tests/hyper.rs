@@ -0,0 +1,107 @@
+extern crate hyper_crate as hyper;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures01::{Future, Stream};
+use hyper::client::HttpConnector;
+use hyper::service::service_fn_ok;
+use hyper::{Body, Client, Response, Server};
+use tls_async::testing::{localhost_builders, localhost_contexts, thread_timer, TestCa};
+use tls_async::{HttpsConnector, HttpsIncoming};
+use tokio::net::TcpListener;
+
+macro_rules! t {
+    ($e:expr) => (match $e {
+        Ok(e) => e,
+        Err(e) => panic!("{} failed with {:?}", stringify!($e), e),
+    })
+}
+
+#[test]
+fn https_round_trip() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, mut connector) = t!(localhost_builders(&ca, &ca));
+    let acceptor = t!(acceptor.build());
+    let connector = t!(connector.request_alpns(&["h2", "http/1.1"]).build());
+
+    let listener = t!(TcpListener::bind(&t!("127.0.0.1:0".parse())));
+    let port = t!(listener.local_addr()).port();
+    let server = Server::builder(HttpsIncoming::new(acceptor, listener.incoming()))
+        .serve(|| service_fn_ok(|_| Response::new(Body::from("hello"))))
+        .map_err(|e| panic!("server failed with {:?}", e));
+
+    let mut http = HttpConnector::new(1);
+    http.enforce_http(false);
+    let client = Client::builder().build::<_, Body>(HttpsConnector::from((http, connector)));
+    let uri = t!(format!("https://localhost:{}/", port).parse());
+    let fut = client.get(uri).and_then(|res| {
+        assert!(res.status().is_success());
+        res.into_body().concat2()
+    });
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    rt.spawn(server);
+    let body = t!(rt.block_on(fut));
+    assert_eq!(&body[..], b"hello");
+}
+
+#[test]
+fn rejects_untrusted_server() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, _) = t!(localhost_contexts(&ca, &ca));
+
+    let listener = t!(TcpListener::bind(&t!("127.0.0.1:0".parse())));
+    let port = t!(listener.local_addr()).port();
+    let server = Server::builder(HttpsIncoming::new(acceptor, listener.incoming()))
+        .serve(|| service_fn_ok(|_| Response::new(Body::from("hello"))))
+        .map_err(|e| panic!("server failed with {:?}", e));
+
+    let client = Client::builder().build::<_, Body>(t!(HttpsConnector::new(1)));
+    let uri = t!(format!("https://localhost:{}/", port).parse());
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    rt.spawn(server);
+    assert!(rt.block_on(client.get(uri)).is_err());
+}
+
+#[test]
+fn stalled_handshake_times_out() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (mut acceptor, connector) = t!(localhost_builders(&ca, &ca));
+    let acceptor = t!(acceptor
+        .timer(Arc::new(thread_timer))
+        .handshake_timeout(Some(Duration::from_millis(100)))
+        .build());
+    let connector = t!(connector.build());
+
+    let listener = t!(TcpListener::bind(&t!("127.0.0.1:0".parse())));
+    let addr = t!(listener.local_addr());
+    let mut incoming = HttpsIncoming::new(acceptor, listener.incoming());
+    incoming.set_max_pending_handshakes(1);
+    let server = Server::builder(incoming)
+        .serve(|| service_fn_ok(|_| Response::new(Body::from("hello"))))
+        .map_err(|e| panic!("server failed with {:?}", e));
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    rt.spawn(server);
+
+    // This client takes the only handshake slot without ever sending a
+    // ClientHello, until the handshake timeout frees it.
+    let stalled = t!(std::net::TcpStream::connect(&addr));
+
+    let mut http = HttpConnector::new(1);
+    http.enforce_http(false);
+    let client = Client::builder().build::<_, Body>(HttpsConnector::from((http, connector)));
+    let uri = t!(format!("https://localhost:{}/", addr.port()).parse());
+    let fut = client.get(uri).and_then(|res| res.into_body().concat2());
+    let body = t!(rt.block_on(fut));
+    assert_eq!(&body[..], b"hello");
+    drop(stalled);
+}
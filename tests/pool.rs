@@ -0,0 +1,288 @@
+#![feature(async_await)]
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::future::poll_fn;
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::{Future, FutureExt, Poll, TryFutureExt};
+use tls_async::testing::{duplex, localhost_contexts, DuplexStream, TestCa, DEFAULT_BUFFER_SIZE};
+use tls_async::{Error, Pool, Pooled, RecordSizing, TlsAcceptor, TlsConnector, TlsStream};
+
+macro_rules! t {
+    ($e:expr) => (match $e {
+        Ok(e) => e,
+        Err(e) => panic!("{} failed with {:?}", stringify!($e), e),
+    })
+}
+
+/// Establishes pooled connections over `duplex` pairs, accepting them on the
+/// runtime and keeping the server ends so tests can close them.
+struct Server {
+    acceptor: TlsAcceptor,
+    connector: TlsConnector,
+    // The server end of each connection, in the order they were made.
+    accepted: Mutex<Vec<Option<oneshot::Receiver<TlsStream<DuplexStream>>>>>,
+}
+
+impl Server {
+    fn new() -> Arc<Server> {
+        let ca = t!(TestCa::new());
+        let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+        Arc::new(Server {
+            acceptor,
+            connector,
+            accepted: Mutex::new(Vec::new()),
+        })
+    }
+
+    async fn get(&self, pool: &Pool<DuplexStream>) -> Result<Pooled<DuplexStream>, Error> {
+        pool.get(&self.connector, "localhost", 443, || async {
+            let (client, server) = duplex(DEFAULT_BUFFER_SIZE);
+            let (tx, rx) = oneshot::channel();
+            let accept = self.acceptor.accept(server);
+            tokio::spawn(async move {
+                drop(tx.send(t!(accept.await)));
+            }.boxed().unit_error().compat());
+            self.accepted.lock().unwrap().push(Some(rx));
+            Ok(client)
+        }).await
+    }
+
+    fn connections(&self) -> usize {
+        self.accepted.lock().unwrap().len()
+    }
+
+    /// Returns the server end of the `index`th connection.
+    async fn accepted(&self, index: usize) -> TlsStream<DuplexStream> {
+        let rx = self.accepted.lock().unwrap()[index].take().expect("connection was taken");
+        t!(rx.await)
+    }
+}
+
+/// Polls `fut` once, returning `true` if it is still pending.
+async fn is_pending<F: Future + Unpin>(fut: &mut F) -> bool {
+    poll_fn(|cx| Poll::Ready(Pin::new(&mut *fut).poll(cx).is_pending())).await
+}
+
+#[test]
+fn reuses_idle_connection() {
+    drop(env_logger::try_init());
+
+    let server = Server::new();
+    let pool = Pool::new();
+    let fut = async move {
+        let mut stream = t!(server.get(&pool).await);
+        assert!(!stream.is_reused());
+        t!(stream.write_all(b"ping").await);
+        drop(stream);
+        assert_eq!(pool.idle_count(), 1);
+
+        let stream = t!(server.get(&pool).await);
+        assert!(stream.is_reused());
+        assert_eq!(server.connections(), 1);
+
+        stream.discard();
+        assert_eq!(pool.idle_count(), 0);
+        let stream = t!(server.get(&pool).await);
+        assert!(!stream.is_reused());
+        assert_eq!(server.connections(), 2);
+    };
+    run(fut);
+}
+
+#[test]
+fn detects_closed_connection() {
+    drop(env_logger::try_init());
+
+    let server = Server::new();
+    let pool = Pool::new();
+    let fut = async move {
+        drop(t!(server.get(&pool).await));
+        let mut accepted = server.accepted(0).await;
+        t!(accepted.close().await);
+
+        let stream = t!(server.get(&pool).await);
+        assert!(!stream.is_reused());
+    };
+    run(fut);
+}
+
+#[test]
+fn expires_idle_connection() {
+    drop(env_logger::try_init());
+
+    let server = Server::new();
+    let pool = Pool::builder().idle_timeout(Some(Duration::from_millis(0))).build();
+    let fut = async move {
+        drop(t!(server.get(&pool).await));
+        let stream = t!(server.get(&pool).await);
+        assert!(!stream.is_reused());
+        assert_eq!(server.connections(), 2);
+
+        // The expired connection was shut down cleanly.
+        let mut expired = server.accepted(0).await;
+        let mut buf = [0; 1];
+        assert_eq!(t!(expired.read(&mut buf).await), 0);
+        assert!(expired.is_peer_closed());
+    };
+    run(fut);
+}
+
+#[test]
+fn buffered_plaintext_is_not_reused() {
+    drop(env_logger::try_init());
+
+    let server = Server::new();
+    let pool = Pool::new();
+    let fut = async move {
+        let mut stream = t!(server.get(&pool).await);
+        stream.set_record_sizing(RecordSizing::Fixed(1000));
+        t!(stream.write_all(b"ping").await);
+        drop(stream);
+        assert_eq!(pool.idle_count(), 0);
+
+        let stream = t!(server.get(&pool).await);
+        assert!(!stream.is_reused());
+
+        // The buffered data went out before close_notify, not to the next
+        // borrower.
+        let mut first = server.accepted(0).await;
+        let mut buf = vec![];
+        t!(first.read_to_end(&mut buf).await);
+        assert_eq!(buf, b"ping");
+    };
+    run(fut);
+}
+
+#[test]
+fn idle_limits() {
+    drop(env_logger::try_init());
+
+    let servers = [Server::new(), Server::new(), Server::new()];
+    let pool = Pool::builder().max_idle_per_host(1).max_idle(2).build();
+    let fut = async move {
+        let first = t!(servers[0].get(&pool).await);
+        let second = t!(servers[0].get(&pool).await);
+        drop(first);
+        drop(second);
+        assert_eq!(pool.idle_count(), 1);
+
+        // Connections made with another connector are kept apart, and evict
+        // the longest idle connection once the global limit is reached.
+        drop(t!(servers[1].get(&pool).await));
+        assert_eq!(pool.idle_count(), 2);
+        drop(t!(servers[2].get(&pool).await));
+        assert_eq!(pool.idle_count(), 2);
+
+        assert!(!t!(servers[0].get(&pool).await).is_reused());
+        assert!(t!(servers[2].get(&pool).await).is_reused());
+    };
+    run(fut);
+}
+
+#[test]
+fn closes_evicted_connection_immediately() {
+    drop(env_logger::try_init());
+
+    let server = Server::new();
+    let pool = Pool::builder().max_idle_per_host(1).build();
+    let fut = async move {
+        let first = t!(server.get(&pool).await);
+        let second = t!(server.get(&pool).await);
+        drop(first);
+        drop(second);
+        assert_eq!(pool.idle_count(), 1);
+        assert_eq!(pool.open_count(), 1);
+
+        // The first connection was shut down cleanly when it was evicted,
+        // without waiting for another call to `get`.
+        let mut evicted = server.accepted(0).await;
+        let mut buf = [0; 1];
+        assert_eq!(t!(evicted.read(&mut buf).await), 0);
+        assert!(evicted.is_peer_closed());
+    };
+    run(fut);
+}
+
+#[test]
+fn per_host_limit_waits_for_connection() {
+    drop(env_logger::try_init());
+
+    let server = Server::new();
+    let other = Server::new();
+    let pool = Pool::builder().max_connections_per_host(Some(1)).build();
+    let fut = async move {
+        let first = t!(server.get(&pool).await);
+        let mut second = Box::pin(server.get(&pool));
+        assert!(is_pending(&mut second).await);
+
+        // Other hosts are not held up.
+        drop(t!(other.get(&pool).await));
+
+        drop(first);
+        let second = t!(second.await);
+        assert!(second.is_reused());
+        assert_eq!(server.connections(), 1);
+
+        // A connection taken out of the pool still counts until dropped.
+        let stream = second.into_inner();
+        let mut third = Box::pin(server.get(&pool));
+        assert!(is_pending(&mut third).await);
+        drop(stream);
+        assert!(!t!(third.await).is_reused());
+        assert_eq!(server.connections(), 2);
+    };
+    run(fut);
+}
+
+#[test]
+fn global_limit_closes_idle_connection_to_make_room() {
+    drop(env_logger::try_init());
+
+    let servers = [Server::new(), Server::new()];
+    let pool = Pool::builder().max_connections(Some(1)).build();
+    let fut = async move {
+        let first = t!(servers[0].get(&pool).await);
+        let mut second = Box::pin(servers[1].get(&pool));
+        assert!(is_pending(&mut second).await);
+
+        // Once idle, the first connection is closed to make room.
+        drop(first);
+        let second = t!(second.await);
+        assert!(!second.is_reused());
+        assert_eq!(pool.idle_count(), 0);
+        assert_eq!(pool.open_count(), 1);
+
+        let mut evicted = servers[0].accepted(0).await;
+        let mut buf = [0; 1];
+        assert_eq!(t!(evicted.read(&mut buf).await), 0);
+        assert!(evicted.is_peer_closed());
+    };
+    run(fut);
+}
+
+#[test]
+fn failed_connect_releases_slot() {
+    drop(env_logger::try_init());
+
+    let server = Server::new();
+    let pool = Pool::builder().max_connections(Some(1)).build();
+    let fut = async move {
+        let res = pool.get(&server.connector, "localhost", 443, || async {
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"))
+        }).await;
+        match res {
+            Err(Error::Connect(ref e)) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+            res => panic!("expected Error::Connect, got {:?}", res.map(|_| ())),
+        }
+        assert_eq!(pool.open_count(), 0);
+
+        let stream = t!(server.get(&pool).await);
+        assert!(!stream.is_reused());
+        assert_eq!(pool.open_count(), 1);
+    };
+    run(fut);
+}
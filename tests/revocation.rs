@@ -0,0 +1,98 @@
+#![feature(async_await)]
+use std::time::{Duration, SystemTime};
+
+use futures::{FutureExt, TryFutureExt};
+use tls_async::testing::{connected_pair, CertificateBuilder, TestCa, TestIdentity};
+use tls_async::{CertificateRevocationList, Error, RevocationPolicy, TlsAcceptor, TlsConnector};
+
+macro_rules! t {
+    ($e:expr) => (match $e {
+        Ok(e) => e,
+        Err(e) => panic!("{} failed with {:?}", stringify!($e), e),
+    })
+}
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn localhost_leaf(ca: &TestCa) -> TestIdentity {
+    t!(ca.leaf(CertificateBuilder::new("localhost").dns_name("localhost")))
+}
+
+fn handshake(ca: &TestCa, leaf: &TestIdentity, crl: Option<String>, policy: RevocationPolicy) -> Result<(), Error> {
+    let acceptor = t!(TlsAcceptor::new(t!(leaf.identity())));
+    let mut connector = TlsConnector::builder();
+    connector.add_root_certificate(t!(ca.certificate()));
+    connector.revocation_policy(policy);
+    if let Some(crl) = crl {
+        connector.add_crl(t!(CertificateRevocationList::from_pem(crl.as_bytes())));
+    }
+    let connector = t!(connector.build());
+    let fut = async move {
+        connected_pair(&connector, "localhost", &acceptor).await.map(|_| ())
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    rt.block_on(fut.boxed().compat())
+}
+
+#[test]
+fn unrevoked_leaf() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let leaf = localhost_leaf(&ca);
+    let other = localhost_leaf(&ca);
+    let crl = t!(ca.crl_pem(&[&other], SystemTime::now() + DAY));
+    t!(handshake(&ca, &leaf, Some(crl), RevocationPolicy::HardFail));
+}
+
+#[test]
+fn revoked_leaf() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let intermediate = t!(ca.intermediate(&CertificateBuilder::new("tls-async test intermediate")));
+    let leaf = localhost_leaf(&intermediate);
+    let crl = t!(intermediate.crl_pem(&[&leaf], SystemTime::now() + DAY));
+    match handshake(&ca, &leaf, Some(crl), RevocationPolicy::SoftFail) {
+        Err(Error::Revoked) => {}
+        res => panic!("expected Error::Revoked, got {:?}", res),
+    }
+}
+
+#[test]
+fn missing_crl() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let other_ca = t!(TestCa::root(&CertificateBuilder::new("tls-async other CA")));
+    let leaf = localhost_leaf(&ca);
+    let crl = t!(other_ca.crl_pem(&[], SystemTime::now() + DAY));
+    match handshake(&ca, &leaf, Some(crl.clone()), RevocationPolicy::HardFail) {
+        Err(Error::RevocationUnavailable(_)) => {}
+        res => panic!("expected Error::RevocationUnavailable, got {:?}", res),
+    }
+    t!(handshake(&ca, &leaf, Some(crl), RevocationPolicy::SoftFail));
+}
+
+#[test]
+fn stale_crl() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let leaf = localhost_leaf(&ca);
+    let crl = t!(ca.crl_pem(&[], SystemTime::now() - Duration::from_secs(60)));
+    match handshake(&ca, &leaf, Some(crl.clone()), RevocationPolicy::HardFail) {
+        Err(Error::RevocationUnavailable(_)) => {}
+        res => panic!("expected Error::RevocationUnavailable, got {:?}", res),
+    }
+    t!(handshake(&ca, &leaf, Some(crl), RevocationPolicy::SoftFail));
+}
+
+#[test]
+fn invalid_crl() {
+    match CertificateRevocationList::from_pem(b"not a CRL") {
+        Err(Error::InvalidCrl(_)) => {}
+        res => panic!("expected Error::InvalidCrl, got {:?}", res),
+    }
+}
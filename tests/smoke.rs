@@ -1,10 +1,10 @@
 #![feature(async_await)]
-use std::io::Write;
-use std::process::Command;
+use std::pin::Pin;
 
 use tls_async::{Identity, TlsAcceptor, TlsConnector};
 use cfg_if::cfg_if;
 use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::future::poll_fn;
 use futures::{FutureExt, StreamExt, TryFutureExt};
 use romio::{TcpStream, TcpListener};
 
@@ -16,234 +16,42 @@ macro_rules! t {
 }
 
 #[allow(dead_code)]
-struct Keys {
-    cert_der: Vec<u8>,
-    pkey_der: Vec<u8>,
-    pkcs12_der: Vec<u8>,
-}
-
-#[allow(dead_code)]
-fn openssl_keys() -> &'static Keys {
-    static INIT: std::sync::Once = std::sync::ONCE_INIT;
-    static mut KEYS: *mut Keys = 0 as *mut _;
-
-    INIT.call_once(|| {
-        let path = t!(env::current_exe());
-        let path = path.parent().unwrap();
-        let keyfile = path.join("test.key");
-        let certfile = path.join("test.crt");
-        let config = path.join("openssl.config");
-
-        File::create(&config).unwrap().write_all(b"\
-            [req]\n\
-            distinguished_name=dn\n\
-            [ dn ]\n\
-            CN=localhost\n\
-            [ ext ]\n\
-            basicConstraints=CA:FALSE,pathlen:0\n\
-            subjectAltName = @alt_names
-            [alt_names]
-            DNS.1 = localhost
-        ").unwrap();
-
-        let subj = "/C=US/ST=Denial/L=Sprintfield/O=Dis/CN=localhost";
-        let output = t!(Command::new("openssl")
-                                .arg("req")
-                                .arg("-nodes")
-                                .arg("-x509")
-                                .arg("-newkey").arg("rsa:2048")
-                                .arg("-config").arg(&config)
-                                .arg("-extensions").arg("ext")
-                                .arg("-subj").arg(subj)
-                                .arg("-keyout").arg(&keyfile)
-                                .arg("-out").arg(&certfile)
-                                .arg("-days").arg("1")
-                                .output());
-        assert!(output.status.success());
-
-        let crtout = t!(Command::new("openssl")
-                                .arg("x509")
-                                .arg("-outform").arg("der")
-                                .arg("-in").arg(&certfile)
-                                .output());
-        assert!(crtout.status.success());
-        let keyout = t!(Command::new("openssl")
-                                .arg("rsa")
-                                .arg("-outform").arg("der")
-                                .arg("-in").arg(&keyfile)
-                                .output());
-        assert!(keyout.status.success());
-
-        let pkcs12out = t!(Command::new("openssl")
-                                   .arg("pkcs12")
-                                   .arg("-export")
-                                   .arg("-nodes")
-                                   .arg("-inkey").arg(&keyfile)
-                                   .arg("-in").arg(&certfile)
-                                   .arg("-password").arg("pass:foobar")
-                                   .output());
-        assert!(pkcs12out.status.success());
-
-        let keys = Box::new(Keys {
-            cert_der: crtout.stdout,
-            pkey_der: keyout.stdout,
-            pkcs12_der: pkcs12out.stdout,
-        });
-        unsafe {
-            KEYS = Box::into_raw(keys);
-        }
-    });
-    unsafe {
-        &*KEYS
-    }
-}
-
-cfg_if! {
-    if #[cfg(feature = "rustls")] {
-        extern crate webpki;
-        extern crate untrusted;
-
-        use std::env;
-        use std::fs::File;
-        use std::process::Command;
-        use std::sync::{ONCE_INIT, Once};
-
-        use untrusted::Input;
-        use webpki::trust_anchor_util;
+fn rcgen_contexts() -> (TlsAcceptor, TlsConnector) {
+    let cert = t!(rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]));
+    let cert_pem = t!(cert.serialize_pem());
+    let key_pem = cert.serialize_private_key_pem();
 
-        fn server_cx() -> io::Result<ServerContext> {
-            let mut cx = ServerContext::new();
+    let identity = t!(Identity::from_pkcs8(cert_pem.as_bytes(), key_pem.as_bytes()));
+    let srv = TlsAcceptor::builder(identity);
 
-            let (cert, key) = keys();
-            cx.config_mut()
-              .set_single_cert(vec![cert.to_vec()], key.to_vec());
+    let mut client = TlsConnector::builder();
+    client.add_root_certificate(t!(tls_async::Certificate::from_pem(cert_pem.as_bytes())));
 
-            Ok(cx)
-        }
-
-        fn configure_client(cx: &mut ClientContext) {
-            let (cert, _key) = keys();
-            let cert = Input::from(cert);
-            let anchor = trust_anchor_util::cert_der_as_trust_anchor(cert).unwrap();
-            cx.config_mut().root_store.add_trust_anchors(&[anchor]);
-        }
-
-        // Like OpenSSL we generate certificates on the fly, but for OSX we
-        // also have to put them into a specific keychain. We put both the
-        // certificates and the keychain next to our binary.
-        //
-        // Right now I don't know of a way to programmatically create a
-        // self-signed certificate, so we just fork out to the `openssl` binary.
-        fn keys() -> (&'static [u8], &'static [u8]) {
-            static INIT: Once = ONCE_INIT;
-            static mut KEYS: *mut (Vec<u8>, Vec<u8>) = 0 as *mut _;
+    (t!(srv.build()), t!(client.build()))
+}
 
-            INIT.call_once(|| {
-                let (key, cert) = openssl_keys();
-                let path = t!(env::current_exe());
-                let path = path.parent().unwrap();
-                let keyfile = path.join("test.key");
-                let certfile = path.join("test.crt");
-                let config = path.join("openssl.config");
-
-                File::create(&config).unwrap().write_all(b"\
-                    [req]\n\
-                    distinguished_name=dn\n\
-                    [ dn ]\n\
-                    CN=localhost\n\
-                    [ ext ]\n\
-                    basicConstraints=CA:FALSE,pathlen:0\n\
-                    subjectAltName = @alt_names
-                    [alt_names]
-                    DNS.1 = localhost
-                ").unwrap();
-
-                let subj = "/C=US/ST=Denial/L=Sprintfield/O=Dis/CN=localhost";
-                let output = t!(Command::new("openssl")
-                                        .arg("req")
-                                        .arg("-nodes")
-                                        .arg("-x509")
-                                        .arg("-newkey").arg("rsa:2048")
-                                        .arg("-config").arg(&config)
-                                        .arg("-extensions").arg("ext")
-                                        .arg("-subj").arg(subj)
-                                        .arg("-keyout").arg(&keyfile)
-                                        .arg("-out").arg(&certfile)
-                                        .arg("-days").arg("1")
-                                        .output());
-                assert!(output.status.success());
-
-                let crtout = t!(Command::new("openssl")
-                                        .arg("x509")
-                                        .arg("-outform").arg("der")
-                                        .arg("-in").arg(&certfile)
-                                        .output());
-                assert!(crtout.status.success());
-                let keyout = t!(Command::new("openssl")
-                                        .arg("rsa")
-                                        .arg("-outform").arg("der")
-                                        .arg("-in").arg(&keyfile)
-                                        .output());
-                assert!(keyout.status.success());
-
-                let cert = crtout.stdout;
-                let key = keyout.stdout;
-                unsafe {
-                    KEYS = Box::into_raw(Box::new((cert, key)));
-                }
-            });
-            unsafe {
-                (&(*KEYS).0, &(*KEYS).1)
-            }
-        }
-    } else if #[cfg(any(feature = "force-openssl",
-                        all(not(target_os = "macos"),
-                            not(target_os = "windows"),
-                            not(target_os = "ios"))))] {
+cfg_if! {
+    if #[cfg(any(feature = "force-openssl",
+                 all(not(target_os = "macos"),
+                     not(target_os = "windows"),
+                     not(target_os = "ios"))))] {
         extern crate openssl;
 
-        use std::fs::File;
-        use std::env;
-        use std::sync::{Once, ONCE_INIT};
-
         fn contexts() -> (TlsAcceptor, TlsConnector) {
-            let keys = openssl_keys();
-
-            let pkcs12 = t!(Identity::from_pkcs12(&keys.pkcs12_der, "foobar"));
-            let srv = TlsAcceptor::builder(pkcs12);
-
-            let cert = t!(tls_async::Certificate::from_der(&keys.cert_der));
-
-            let mut client = TlsConnector::builder();
-            t!(client.add_root_certificate(cert).build());
-
-            (t!(srv.build()).into(), t!(client.build()).into())
+            rcgen_contexts()
         }
     } else if #[cfg(any(target_os = "macos", target_os = "ios"))] {
         extern crate security_framework;
 
-        use std::env;
-        use std::fs::File;
-
         fn contexts() -> (TlsAcceptor, TlsConnector) {
-            let keys = openssl_keys();
-
-            let pkcs12 = t!(Identity::from_pkcs12(&keys.pkcs12_der, "foobar"));
-            let srv = TlsAcceptor::builder(pkcs12);
-
-            let cert = tls_async::Certificate::from_der(&keys.cert_der).unwrap();
-            let mut client = TlsConnector::builder();
-            client.add_root_certificate(cert);
-
-            (t!(srv.build()).into(), t!(client.build()).into())
+            rcgen_contexts()
         }
     } else {
         extern crate schannel;
         extern crate winapi;
 
         use std::env;
-        use std::fs::File;
-        use std::io::Error;
+        use std::io::{self, Error, Write};
         use std::mem;
         use std::ptr;
         use std::sync::{Once, ONCE_INIT};
@@ -590,3 +398,104 @@ fn one_byte_at_a_time() {
 
     assert!(data == SMALL_EXPECTED.to_vec());
 }
+
+/// Reads from a server which drops its stream without sending
+/// `close_notify`, returning the outcome of `read_to_end` and whether the
+/// client saw the connection as truncated. `require_close_notify` overrides
+/// the connector's default, if set.
+fn read_truncated(require_close_notify: Option<bool>) -> (Result<usize, std::io::ErrorKind>, bool) {
+    let mut srv = t!(TcpListener::bind(&t!("127.0.0.1:0".parse())));
+    let addr = t!(srv.local_addr());
+    let (server_cx, client_cx) = contexts();
+
+    let fut_server = async move {
+        let mut incoming = srv.incoming();
+        let socket = t!(incoming.next().await.unwrap());
+        let mut stream = t!(server_cx.accept(socket).await);
+        t!(stream.write_all(&SMALL_EXPECTED).await);
+        t!(stream.flush().await);
+        // Dropping the stream without closing it never sends close_notify.
+        drop(stream);
+    };
+
+    let fut_client = async move {
+        let socket = t!(TcpStream::connect(&addr).await);
+        let mut stream = t!(client_cx.connect("localhost", socket).await);
+        if let Some(require_close_notify) = require_close_notify {
+            stream.set_require_close_notify(require_close_notify);
+        }
+        let mut buf = vec![];
+        let res = stream.read_to_end(&mut buf).await;
+        (res.map_err(|e| e.kind()), stream.is_truncated())
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    rt.spawn(fut_server.boxed().unit_error().compat());
+    t!(rt.block_on(fut_client.boxed().unit_error().compat()))
+}
+
+#[test]
+fn truncated_connection() {
+    drop(env_logger::try_init());
+
+    let (res, truncated) = read_truncated(None);
+    assert_eq!(res, Err(std::io::ErrorKind::UnexpectedEof));
+    assert!(truncated);
+}
+
+#[test]
+fn truncated_connection_tolerated() {
+    drop(env_logger::try_init());
+
+    let (res, truncated) = read_truncated(Some(false));
+    assert_eq!(res, Ok(SMALL_EXPECTED.len()));
+    assert!(truncated);
+}
+
+#[test]
+fn truncated_connection_requiring_close_notify() {
+    drop(env_logger::try_init());
+
+    let (res, truncated) = read_truncated(Some(true));
+    assert_eq!(res, Err(std::io::ErrorKind::UnexpectedEof));
+    assert!(truncated);
+}
+
+#[test]
+fn half_close() {
+    drop(env_logger::try_init());
+
+    let mut srv = t!(TcpListener::bind(&t!("127.0.0.1:0".parse())));
+    let addr = t!(srv.local_addr());
+    let (server_cx, client_cx) = contexts();
+
+    let fut_server = async move {
+        let mut incoming = srv.incoming();
+        let socket = t!(incoming.next().await.unwrap());
+        let mut stream = t!(server_cx.accept(socket).await);
+        let mut buf = vec![];
+        t!(stream.read_to_end(&mut buf).await);
+        assert!(stream.is_peer_closed());
+        assert!(!stream.is_truncated());
+        t!(stream.write_all(&buf).await);
+        t!(stream.close().await);
+    };
+
+    let fut_client = async move {
+        let socket = t!(TcpStream::connect(&addr).await);
+        let mut stream = t!(client_cx.connect("localhost", socket).await);
+        t!(stream.write_all(&SMALL_EXPECTED).await);
+        t!(poll_fn(|cx| Pin::new(&mut stream).poll_shutdown_write(cx)).await);
+        assert!(stream.write_all(&SMALL_EXPECTED).await.is_err());
+        let mut buf = vec![];
+        t!(stream.read_to_end(&mut buf).await);
+        assert!(stream.is_peer_closed());
+        buf
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    rt.spawn(fut_server.boxed().unit_error().compat());
+    let data = t!(rt.block_on(fut_client.boxed().unit_error().compat()));
+
+    assert!(data == SMALL_EXPECTED.to_vec());
+}
@@ -0,0 +1,133 @@
+#![feature(async_await)]
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use futures::{FutureExt, TryFutureExt};
+use tls_async::testing::{connected_pair, localhost_contexts, TestCa};
+use tokio::runtime::current_thread::Runtime;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+macro_rules! t {
+    ($e:expr) => (match $e {
+        Ok(e) => e,
+        Err(e) => panic!("{} failed with {:?}", stringify!($e), e),
+    })
+}
+
+type Fields = HashMap<&'static str, String>;
+
+/// A subscriber keeping the fields recorded on every span.
+#[derive(Clone, Default)]
+struct Recorder {
+    spans: Arc<Mutex<Vec<Fields>>>,
+}
+
+impl Recorder {
+    /// Returns the fields of the connection spans with the given role.
+    fn connections(&self, role: &str) -> Vec<Fields> {
+        let spans = self.spans.lock().unwrap();
+        spans.iter().filter(|fields| fields.get("role").map(|r| r.as_str()) == Some(role)).cloned().collect()
+    }
+}
+
+struct Visitor<'a>(&'a mut Fields);
+
+impl<'a> Visit for Visitor<'a> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut spans = self.spans.lock().unwrap();
+        let mut fields = Fields::new();
+        span.record(&mut Visitor(&mut fields));
+        spans.push(fields);
+        Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut spans = self.spans.lock().unwrap();
+        values.record(&mut Visitor(&mut spans[span.into_u64() as usize - 1]));
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+/// Runs `fut` on the current thread, where `recorder` is the subscriber.
+fn run<F: std::future::Future<Output = ()> + Send + 'static>(recorder: &Recorder, fut: F) {
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let mut rt = t!(Runtime::new());
+        t!(rt.block_on(fut.boxed().unit_error().compat()));
+    });
+}
+
+#[test]
+fn span_fields() {
+    drop(env_logger::try_init());
+
+    let recorder = Recorder::default();
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    run(&recorder, async move {
+        t!(connected_pair(&connector, "localhost", &acceptor).await);
+    });
+
+    let clients = recorder.connections("Client");
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].get("sni").map(|s| s.as_str()), Some("localhost"));
+    assert_eq!(clients[0].get("peer.addr"), None);
+
+    // `native-tls` does not report the name requested by the client.
+    let servers = recorder.connections("Server");
+    assert_eq!(servers.len(), 1);
+    assert_eq!(servers[0].get("sni"), None);
+}
+
+#[cfg(feature = "romio")]
+#[test]
+fn connect_to_records_peer_addr() {
+    use futures::StreamExt;
+    use romio::TcpListener;
+
+    drop(env_logger::try_init());
+
+    let recorder = Recorder::default();
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let mut srv = t!(TcpListener::bind(&t!("127.0.0.1:0".parse())));
+    let addr = t!(srv.local_addr());
+    run(&recorder, async move {
+        let fut_server = async move {
+            let mut incoming = srv.incoming();
+            let socket = t!(incoming.next().await.unwrap());
+            t!(acceptor.accept(socket).await);
+        };
+        let fut_client = async move {
+            t!(connector.connect_to(&format!("localhost:{}", addr.port())).await);
+        };
+        futures::future::join(fut_server, fut_client).await;
+    });
+
+    let clients = recorder.connections("Client");
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].get("peer.addr"), Some(&addr.to_string()));
+}
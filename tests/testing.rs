@@ -0,0 +1,850 @@
+#![feature(async_await)]
+use std::io;
+use std::marker::PhantomPinned;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Context;
+use std::time::{Duration, SystemTime};
+
+use futures::future::poll_fn;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, IoSlice, IoSliceMut};
+use futures::{FutureExt, Poll, TryFutureExt};
+use tls_async::testing::{
+    connected_pair, duplex, localhost_builders, localhost_contexts, thread_timer, CertificateBuilder, DuplexStream,
+    TestCa, DEFAULT_BUFFER_SIZE,
+};
+use tls_async::{AtomicMetrics, Certificate, Error, Principal, RecordSizing, TlsAcceptor, TlsConnector};
+
+macro_rules! t {
+    ($e:expr) => (match $e {
+        Ok(e) => e,
+        Err(e) => panic!("{} failed with {:?}", stringify!($e), e),
+    })
+}
+
+const EXPECTED: [u8; 40_000] = [7u8; 40_000];
+
+#[test]
+fn duplex_round_trip() {
+    let fut = async {
+        let (mut a, mut b) = duplex(4);
+        let write = async move {
+            t!(a.write_all(b"hello world").await);
+            t!(a.close().await);
+        };
+        let read = async move {
+            let mut buf = vec![];
+            t!(b.read_to_end(&mut buf).await);
+            buf
+        };
+        let ((), buf) = futures::future::join(write, read).await;
+        buf
+    };
+
+    let buf = futures::executor::block_on(fut);
+    assert_eq!(buf, b"hello world");
+}
+
+#[test]
+fn tls_over_duplex() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let fut = async move {
+        let (mut client, mut server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        t!(client.write_all(b"ping").await);
+        t!(client.close().await);
+        let mut buf = vec![];
+        t!(server.read_to_end(&mut buf).await);
+        buf
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let buf = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert_eq!(buf, b"ping");
+}
+
+#[test]
+fn tls_on_any_executor() {
+    drop(env_logger::try_init());
+
+    // The small buffers make every step of the handshake and the transfer
+    // wait for the other side, which only works if the transport wakes the
+    // task that last polled the stream.
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let fut = async move {
+        let (a, b) = duplex(64);
+        let (mut client, mut server) = t!(futures::future::try_join(
+            connector.connect("localhost", a),
+            acceptor.accept(b),
+        ).await);
+        let write = async move {
+            t!(client.write_all(&EXPECTED).await);
+            t!(client.close().await);
+        };
+        let read = async move {
+            let mut buf = vec![];
+            t!(server.read_to_end(&mut buf).await);
+            buf
+        };
+        let ((), buf) = futures::future::join(write, read).await;
+        buf
+    };
+
+    let buf = futures::executor::block_on(fut);
+    assert_eq!(&buf[..], &EXPECTED[..]);
+}
+
+#[test]
+fn tls_in_tls() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let fut = async move {
+        let (outer_client, outer_server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        let inner = futures::future::try_join(
+            connector.connect("localhost", outer_client),
+            acceptor.accept(outer_server),
+        );
+        let (mut client, mut server) = t!(inner.await);
+        t!(client.write_all(b"ping").await);
+        t!(client.close().await);
+        let mut buf = vec![];
+        t!(server.read_to_end(&mut buf).await);
+        buf
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let buf = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert_eq!(buf, b"ping");
+}
+
+/// A transport which is not `Unpin`, delegating to a `DuplexStream`.
+struct Pinned {
+    inner: DuplexStream,
+    _pinned: PhantomPinned,
+}
+
+impl Pinned {
+    fn inner(self: Pin<&mut Self>) -> Pin<&mut DuplexStream> {
+        // `inner` is never pinned structurally.
+        unsafe { Pin::new(&mut self.get_unchecked_mut().inner) }
+    }
+}
+
+impl AsyncRead for Pinned {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.inner().poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Pinned {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.inner().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner().poll_close(cx)
+    }
+}
+
+fn assert_unpin<T: Unpin>(_: &T) {}
+
+#[test]
+fn pinned_transport() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let (client, server) = duplex(DEFAULT_BUFFER_SIZE);
+    let client = Pinned { inner: client, _pinned: PhantomPinned };
+    let server = Pinned { inner: server, _pinned: PhantomPinned };
+    let fut = async move {
+        let handshake = futures::future::try_join(
+            connector.connect("localhost", client),
+            acceptor.accept(server),
+        );
+        let (mut client, mut server) = t!(handshake.await);
+        assert_unpin(&client);
+        t!(client.write_all(b"ping").await);
+        t!(client.close().await);
+        let mut buf = vec![];
+        t!(server.read_to_end(&mut buf).await);
+        buf
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let buf = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert_eq!(buf, b"ping");
+}
+
+#[test]
+fn leaf_signed_by_intermediate() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let intermediate = t!(ca.intermediate(&CertificateBuilder::new("tls-async test intermediate")));
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &intermediate));
+    let fut = async move {
+        connected_pair(&connector, "localhost", &acceptor).await.map(|_| ())
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    t!(rt.block_on(fut.boxed().compat()));
+}
+
+#[test]
+fn recover_transport_from_plaintext_client() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (mut acceptor, _) = t!(localhost_builders(&ca, &ca));
+    let acceptor = t!(acceptor.recover_transport(true).build());
+    let (mut client, server) = duplex(DEFAULT_BUFFER_SIZE);
+    let fut_server = async move {
+        let mut pending = acceptor.accept(server);
+        assert!((&mut pending).await.is_err());
+        let (mut server, bytes_read) = pending.take_transport().unwrap().into_inner();
+        t!(server.write_all(b"HTTP/1.0 400 Bad Request\r\n\r\n").await);
+        t!(server.close().await);
+        bytes_read
+    };
+    let fut_client = async move {
+        t!(client.write_all(b"GET / HTTP/1.0\r\n\r\n").await);
+        let mut buf = vec![];
+        t!(client.read_to_end(&mut buf).await);
+        buf
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let fut = futures::future::join(fut_server, fut_client);
+    let (bytes_read, response) = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert!(bytes_read.starts_with(b"GET"), "read {:?}", bytes_read);
+    // Some backends send an alert before giving up on the handshake.
+    assert!(response.ends_with(b"HTTP/1.0 400 Bad Request\r\n\r\n"));
+}
+
+#[test]
+fn authorizer_assigns_principal() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (mut acceptor, connector) = t!(localhost_builders(&ca, &ca));
+    let acceptor = t!(acceptor
+        .authorizer(|peer_certificate: Option<&Certificate>| {
+            assert!(peer_certificate.is_none());
+            Ok(Principal::new("anonymous"))
+        })
+        .build());
+    let connector = t!(connector.build());
+    let fut = async move {
+        let (client, server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        assert!(client.principal().is_none());
+        let principal = server.principal().expect("no principal");
+        assert_eq!(principal.downcast_ref::<&str>(), Some(&"anonymous"));
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    t!(rt.block_on(fut.boxed().unit_error().compat()));
+}
+
+#[test]
+fn authorizer_rejects_client() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (mut acceptor, connector) = t!(localhost_builders(&ca, &ca));
+    let acceptor = t!(acceptor
+        .authorizer(|_: Option<&Certificate>| Err("no client certificate".to_owned()))
+        .build());
+    let connector = t!(connector.build());
+    let (client, server) = duplex(DEFAULT_BUFFER_SIZE);
+    let fut = async move {
+        // The client completes its handshake before the server rejects it.
+        let client = connector.connect("localhost", client);
+        let server = acceptor.accept(server);
+        let (client, server) = futures::future::join(client, server).await;
+        (client.map(|_| ()), server.map(|_| ()))
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let (client, server) = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    t!(client);
+    match server {
+        Err(Error::Unauthorized(ref reason)) if reason == "no client certificate" => {}
+        res => panic!("expected the client to be rejected, got {:?}", res),
+    }
+}
+
+#[test]
+fn expired_leaf() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let now = SystemTime::now();
+    let day = Duration::from_secs(24 * 60 * 60);
+    let leaf = t!(ca.leaf(CertificateBuilder::new("localhost")
+        .dns_name("localhost")
+        .validity(now - 2 * day, now - day)));
+    let acceptor = t!(TlsAcceptor::new(t!(leaf.identity())));
+    let mut connector = TlsConnector::builder();
+    connector.add_root_certificate(t!(ca.certificate()));
+    let connector = t!(connector.build());
+    let fut = async move {
+        connected_pair(&connector, "localhost", &acceptor).await.map(|_| ())
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    assert!(rt.block_on(fut.boxed().compat()).is_err());
+}
+
+#[test]
+fn buffered_record_sizing() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let fut = async move {
+        let (mut client, mut server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        client.set_record_sizing(RecordSizing::Dynamic { initial: 100 });
+        server.set_record_sizing(RecordSizing::Fixed(1000));
+        for chunk in EXPECTED.chunks(7) {
+            t!(client.write_all(chunk).await);
+        }
+        t!(client.flush().await);
+        let mut buf = vec![0; EXPECTED.len()];
+        t!(server.read_exact(&mut buf).await);
+        t!(server.write_all(&buf).await);
+        t!(server.close().await);
+        let mut echoed = vec![];
+        t!(client.read_to_end(&mut echoed).await);
+        echoed
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let buf = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert_eq!(&buf[..], &EXPECTED[..]);
+}
+
+/// A transport recording the length of every TLS record written through it.
+struct RecordLengths {
+    inner: DuplexStream,
+    lengths: Arc<Mutex<Vec<usize>>>,
+    // Bytes of the current record header seen so far, and bytes of the
+    // current record body still to come.
+    header: Vec<u8>,
+    remaining: usize,
+}
+
+impl AsyncRead for RecordLengths {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for RecordLengths {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let sz = match Pin::new(&mut self.inner).poll_write(cx, buf) {
+            Poll::Ready(Ok(sz)) => sz,
+            other => return other,
+        };
+        let mut written = &buf[..sz];
+        while !written.is_empty() {
+            if self.remaining > 0 {
+                let n = std::cmp::min(self.remaining, written.len());
+                self.remaining -= n;
+                written = &written[n..];
+                continue;
+            }
+            self.header.push(written[0]);
+            written = &written[1..];
+            if self.header.len() == 5 {
+                self.remaining = (self.header[3] as usize) << 8 | self.header[4] as usize;
+                self.header.clear();
+                let remaining = self.remaining;
+                self.lengths.lock().unwrap().push(remaining);
+            }
+        }
+        Poll::Ready(Ok(sz))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[test]
+fn dynamic_record_size_resets_on_flush() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let lengths = Arc::new(Mutex::new(Vec::new()));
+    let (client, server) = duplex(DEFAULT_BUFFER_SIZE);
+    let client = RecordLengths {
+        inner: client,
+        lengths: lengths.clone(),
+        header: Vec::new(),
+        remaining: 0,
+    };
+    let message = EXPECTED[..2_000].to_vec();
+    let recorded = lengths.clone();
+    let fut = async move {
+        let handshake = futures::future::try_join(
+            connector.connect("localhost", client),
+            acceptor.accept(server),
+        );
+        let (mut client, mut server) = t!(handshake.await);
+        client.set_record_sizing(RecordSizing::Dynamic { initial: 100 });
+        let mut buf = vec![0; message.len()];
+        let mut records = vec![];
+        for _ in 0..2 {
+            recorded.lock().unwrap().clear();
+            t!(client.write_all(&message).await);
+            t!(client.flush().await);
+            t!(server.read_exact(&mut buf).await);
+            records.push(recorded.lock().unwrap().clone());
+        }
+        records
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let records = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert!(records[0].len() > 1);
+    assert_eq!(records[0], records[1]);
+}
+
+/// Writes all of `bufs` through `poll_write_vectored`, which may only write
+/// a prefix of them each time.
+async fn write_all_vectored<W: AsyncWrite + Unpin>(stream: &mut W, bufs: &[&[u8]]) {
+    let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+    let mut written = 0;
+    while written < total {
+        let mut skip = written;
+        let slices: Vec<IoSlice<'_>> = bufs.iter().filter_map(|buf| {
+            if skip >= buf.len() {
+                skip -= buf.len();
+                return None;
+            }
+            let slice = IoSlice::new(&buf[skip..]);
+            skip = 0;
+            Some(slice)
+        }).collect();
+        let sz = t!(poll_fn(|cx| Pin::new(&mut *stream).poll_write_vectored(cx, &slices)).await);
+        assert!(sz > 0);
+        written += sz;
+    }
+}
+
+/// Reads until EOF through `poll_read_vectored`, into slices of uneven sizes.
+async fn read_to_end_vectored<R: AsyncRead + Unpin>(stream: &mut R) -> Vec<u8> {
+    let mut res = vec![];
+    loop {
+        let (mut a, mut b, mut c) = ([0; 10], [0; 100], vec![0; 50_000]);
+        let sz = {
+            let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b), IoSliceMut::new(&mut c)];
+            t!(poll_fn(|cx| Pin::new(&mut *stream).poll_read_vectored(cx, &mut bufs)).await)
+        };
+        if sz == 0 {
+            return res;
+        }
+        res.extend(a.iter().chain(b.iter()).chain(c.iter()).take(sz));
+    }
+}
+
+#[test]
+fn vectored_round_trip() {
+    drop(env_logger::try_init());
+
+    let header = b"HTTP/1.1 200 OK\r\nContent-Length: 40004\r\n\r\n";
+    let body: Vec<u8> = (0..40_000).map(|i| i as u8).collect();
+    let trailer = b"done";
+    let mut expected = header.to_vec();
+    expected.extend_from_slice(&body);
+    expected.extend_from_slice(trailer);
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let fut = async move {
+        let (mut client, mut server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        let write = async move {
+            write_all_vectored(&mut client, &[&header[..], &body[..], &trailer[..]]).await;
+            t!(client.close().await);
+        };
+        let read = async move {
+            read_to_end_vectored(&mut server).await
+        };
+        futures::future::join(write, read).await.1
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let buf = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert_eq!(buf, expected);
+}
+
+#[test]
+fn vectored_read_keeps_error() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let fut = async move {
+        let (mut client, mut server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        client.set_require_close_notify(true);
+        t!(server.write_all(b"hello").await);
+        t!(server.flush().await);
+        // Dropping the stream without closing it never sends close_notify.
+        drop(server);
+
+        // The first slice is filled exactly, so the second read hits the
+        // truncation after data was already read.
+        let (mut a, mut b) = ([0; 5], [0; 10]);
+        let sz = {
+            let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+            t!(poll_fn(|cx| Pin::new(&mut client).poll_read_vectored(cx, &mut bufs)).await)
+        };
+        assert_eq!(&a[..sz], b"hello");
+        let mut buf = [0; 16];
+        client.read(&mut buf).await.map_err(|e| e.kind())
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let res = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert_eq!(res, Err(std::io::ErrorKind::UnexpectedEof));
+}
+
+/// A transport flipping a bit of the next TLS record body it reads once
+/// `corrupt` is set.
+struct Corrupting {
+    inner: DuplexStream,
+    corrupt: Arc<AtomicBool>,
+}
+
+impl AsyncRead for Corrupting {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let sz = match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(sz)) => sz,
+            other => return other,
+        };
+        // Record headers are read on their own, and must stay intact so the
+        // whole record is read.
+        if sz > 5 && self.corrupt.swap(false, Ordering::SeqCst) {
+            buf[sz - 1] ^= 1;
+        }
+        Poll::Ready(Ok(sz))
+    }
+}
+
+impl AsyncWrite for Corrupting {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[test]
+fn corrupt_final_record_is_an_error() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let (client, server) = duplex(DEFAULT_BUFFER_SIZE);
+    let corrupt = Arc::new(AtomicBool::new(false));
+    let client = Corrupting { inner: client, corrupt: corrupt.clone() };
+    let fut = async move {
+        let handshake = futures::future::try_join(
+            connector.connect("localhost", client),
+            acceptor.accept(server),
+        );
+        let (mut client, mut server) = t!(handshake.await);
+        // Even when a missing close_notify is tolerated, a record failing
+        // its integrity check must not look like the end of the stream.
+        client.set_require_close_notify(false);
+        corrupt.store(true, Ordering::SeqCst);
+        t!(server.write_all(b"hello").await);
+        t!(server.flush().await);
+        drop(server);
+        let mut buf = vec![];
+        client.read_to_end(&mut buf).await.map_err(|e| e.kind())
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    match t!(rt.block_on(fut.boxed().unit_error().compat())) {
+        Err(kind) => assert_ne!(kind, std::io::ErrorKind::UnexpectedEof),
+        Ok(sz) => panic!("read {} bytes from a corrupt record", sz),
+    }
+}
+
+#[test]
+fn metrics_are_reported() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (mut acceptor, mut connector) = t!(localhost_builders(&ca, &ca));
+    let server_metrics = Arc::new(AtomicMetrics::new());
+    let acceptor = t!(acceptor.metrics(server_metrics.clone()).build());
+    let client_metrics = Arc::new(AtomicMetrics::new());
+    let connector = t!(connector.metrics(client_metrics.clone()).build());
+
+    // A client not trusting the test CA rejects the server's certificate.
+    let untrusted_metrics = Arc::new(AtomicMetrics::new());
+    let untrusted = t!(TlsConnector::builder()
+        .metrics(untrusted_metrics.clone())
+        .build());
+
+    let fut = async move {
+        let (mut client, mut server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        t!(client.write_all(b"ping").await);
+        t!(client.close().await);
+        let mut buf = vec![];
+        t!(server.read_to_end(&mut buf).await);
+
+        assert!(connected_pair(&untrusted, "localhost", &acceptor).await.is_err());
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    t!(rt.block_on(fut.boxed().unit_error().compat()));
+
+    assert_eq!(client_metrics.handshakes_started(), 1);
+    assert_eq!(client_metrics.handshakes_succeeded(), 1);
+    assert_eq!(client_metrics.bytes_written(), 4);
+    assert_eq!(server_metrics.handshakes_started(), 2);
+    assert_eq!(server_metrics.handshakes_succeeded(), 1);
+    // `connected_pair` drops the server's handshake once the client fails.
+    assert_eq!(server_metrics.handshakes_failed(), 0);
+    assert_eq!(server_metrics.handshakes_cancelled(), 1);
+    assert_eq!(server_metrics.bytes_read(), 4);
+    assert_eq!(untrusted_metrics.handshakes_started(), 1);
+    assert_eq!(untrusted_metrics.handshakes_failed(), 1);
+    assert_eq!(untrusted_metrics.handshakes_cancelled(), 0);
+}
+
+#[test]
+fn read_timeout() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let fut = async move {
+        let (mut client, _server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        client.set_timer(Arc::new(thread_timer));
+        client.set_read_timeout(Some(Duration::from_millis(50)));
+        let mut buf = [0; 16];
+        client.read(&mut buf).await.map_err(|e| e.kind())
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let res = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert_eq!(res, Err(std::io::ErrorKind::TimedOut));
+}
+
+#[test]
+fn write_timeout() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let fut = async move {
+        let (mut client, _server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        client.set_timer(Arc::new(thread_timer));
+        client.set_write_timeout(Some(Duration::from_millis(50)));
+        // The server never reads, so the transport fills up.
+        let buf = vec![0; 4 * DEFAULT_BUFFER_SIZE];
+        client.write_all(&buf).await.map_err(|e| e.kind())
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let res = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert_eq!(res, Err(std::io::ErrorKind::TimedOut));
+}
+
+#[test]
+fn idle_timeout() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let fut = async move {
+        let (mut client, _server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        client.set_timer(Arc::new(thread_timer));
+        client.set_idle_timeout(Some(Duration::from_millis(50)));
+        let mut buf = [0; 16];
+        client.read(&mut buf).await.map_err(|e| e.to_string())
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let res = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert_eq!(res, Err("TLS connection idle timeout".to_owned()));
+}
+
+#[test]
+fn builder_timeouts() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (mut acceptor, mut connector) = t!(localhost_builders(&ca, &ca));
+    let acceptor = t!(acceptor
+        .timer(Arc::new(thread_timer))
+        .write_timeout(Some(Duration::from_millis(50)))
+        .build());
+    let connector = t!(connector
+        .timer(Arc::new(thread_timer))
+        .idle_timeout(Some(Duration::from_secs(60)))
+        .read_timeout(Some(Duration::from_millis(50)))
+        .build());
+    let fut = async move {
+        let (mut client, mut server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        assert_eq!(client.idle_timeout(), Some(Duration::from_secs(60)));
+        assert_eq!(client.read_timeout(), Some(Duration::from_millis(50)));
+        assert_eq!(client.write_timeout(), None);
+        assert_eq!(server.write_timeout(), Some(Duration::from_millis(50)));
+
+        let mut buf = [0; 16];
+        let read = client.read(&mut buf).await.map_err(|e| e.kind());
+        // The client never reads, so the transport fills up.
+        let write = server.write_all(&vec![0; 4 * DEFAULT_BUFFER_SIZE]).await.map_err(|e| e.kind());
+        (read, write)
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let (read, write) = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert_eq!(read, Err(std::io::ErrorKind::TimedOut));
+    assert_eq!(write, Err(std::io::ErrorKind::TimedOut));
+}
+
+/// Starts a read with a 100ms timeout and gives up on it, then starts
+/// another read `gap` later, which gets its data after 30ms.
+fn read_after_abandoned_read(gap: Duration) -> Vec<u8> {
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let fut = async move {
+        let (mut client, mut server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        client.set_timer(Arc::new(thread_timer));
+        client.set_read_timeout(Some(Duration::from_millis(100)));
+        let read = async move {
+            let mut buf = [0; 16];
+            poll_fn(|cx| {
+                assert!(Pin::new(&mut client).poll_read(cx, &mut buf).is_pending());
+                Poll::Ready(())
+            }).await;
+            client.reset_timeouts();
+            thread_timer(gap).await;
+
+            // This read only waits for 30ms, well within its own timeout.
+            let sz = t!(client.read(&mut buf).await);
+            buf[..sz].to_vec()
+        };
+        let write = async move {
+            thread_timer(gap + Duration::from_millis(30)).await;
+            t!(server.write_all(b"hello").await);
+            t!(server.flush().await);
+            server
+        };
+        futures::future::join(read, write).await.0
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    t!(rt.block_on(fut.boxed().unit_error().compat()))
+}
+
+#[test]
+fn abandoned_read_timeout() {
+    drop(env_logger::try_init());
+
+    // The abandoned read's delay elapses during the gap.
+    assert_eq!(read_after_abandoned_read(Duration::from_millis(300)), b"hello");
+}
+
+#[test]
+fn abandoned_read_timeout_short_gap() {
+    drop(env_logger::try_init());
+
+    // The new read would hit the abandoned read's deadline while waiting.
+    assert_eq!(read_after_abandoned_read(Duration::from_millis(150)), b"hello");
+}
+
+#[test]
+fn vectored_read_restarts_timeouts() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (acceptor, connector) = t!(localhost_contexts(&ca, &ca));
+    let fut = async move {
+        let (mut client, mut server) = t!(connected_pair(&connector, "localhost", &acceptor).await);
+        client.set_timer(Arc::new(thread_timer));
+        client.set_read_timeout(Some(Duration::from_millis(100)));
+        t!(server.write_all(b"hello").await);
+        t!(server.flush().await);
+        let read = async move {
+            // The first slice is filled exactly, so reading into the second
+            // one waits, starting the timeouts, before the data is returned.
+            let (mut a, mut b) = ([0; 5], [0; 10]);
+            let sz = {
+                let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+                t!(poll_fn(|cx| Pin::new(&mut client).poll_read_vectored(cx, &mut bufs)).await)
+            };
+            assert_eq!(&a[..sz], b"hello");
+            thread_timer(Duration::from_millis(80)).await;
+
+            // This read waits for 60ms, past the deadline the vectored read
+            // would have left behind.
+            let mut buf = [0; 16];
+            let sz = t!(client.read(&mut buf).await);
+            buf[..sz].to_vec()
+        };
+        let write = async move {
+            thread_timer(Duration::from_millis(140)).await;
+            t!(server.write_all(b"world").await);
+            t!(server.flush().await);
+            server
+        };
+        futures::future::join(read, write).await.0
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    let buf = t!(rt.block_on(fut.boxed().unit_error().compat()));
+    assert_eq!(buf, b"world");
+}
+
+#[test]
+fn handshake_timeout() {
+    drop(env_logger::try_init());
+
+    let ca = t!(TestCa::new());
+    let (mut acceptor, _) = t!(localhost_builders(&ca, &ca));
+    let acceptor = t!(acceptor
+        .timer(Arc::new(thread_timer))
+        .handshake_timeout(Some(Duration::from_millis(50)))
+        .build());
+    // The client never starts its handshake.
+    let (client, server) = duplex(DEFAULT_BUFFER_SIZE);
+    let fut = async move {
+        acceptor.accept(server).await.map(|_| ())
+    };
+
+    let mut rt = t!(tokio::runtime::Runtime::new());
+    match rt.block_on(fut.boxed().compat()) {
+        Err(Error::HandshakeTimeout) => {}
+        res => panic!("expected a handshake timeout, got {:?}", res),
+    }
+    drop(client);
+}